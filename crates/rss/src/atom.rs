@@ -0,0 +1,119 @@
+use crate::{read::Channel, xml::XmlWriter};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::{self, Write};
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+/// Render a channel read back from RSS as an Atom 1.0 feed published at
+/// `self_url`. Atom needs an author, so items without `dc:creator` fall
+/// back to the channel generator
+pub fn build(channel: &Channel, self_url: &str) -> io::Result<String> {
+    let mut atom = Vec::with_capacity(channel.items.len() * 600 + 600);
+    write_to(channel, self_url, &mut atom)?;
+    String::from_utf8(atom).map_err(io::Error::other)
+}
+
+/// Stream the Atom document to a writer
+pub fn write_to<W: Write>(channel: &Channel, self_url: &str, writer: W) -> io::Result<()> {
+    let mut xml = XmlWriter::new(writer, true);
+    xml.declaration()?;
+    xml.start("feed", &[("xmlns", ATOM_NS)])?;
+
+    let updated = channel
+        .last_build_date
+        .or(channel.pub_date)
+        .unwrap_or_else(Utc::now);
+    xml.text("id", self_url)?;
+    xml.text("title", &channel.title)?;
+    xml.text("subtitle", &channel.description)?;
+    xml.text("updated", &timestamp(updated))?;
+    xml.empty(
+        "link",
+        &[
+            ("rel", "self"),
+            ("type", "application/atom+xml"),
+            ("href", self_url),
+        ],
+    )?;
+    xml.empty("link", &[("rel", "alternate"), ("href", &channel.link)])?;
+
+    let generator = channel.generator.as_deref().unwrap_or("rss");
+    xml.start("author", &[])?;
+    xml.text("name", generator)?;
+    xml.end("author")?;
+    xml.text("generator", generator)?;
+    if let Some(image) = &channel.image {
+        xml.text("logo", &image.url)?;
+    }
+    for category in &channel.categories {
+        xml.empty("category", &[("term", category)])?;
+    }
+
+    for item in &channel.items {
+        let published = item.pub_date.unwrap_or(updated);
+        xml.start("entry", &[])?;
+        xml.text("id", &item.guid)?;
+        xml.text("title", &item.title)?;
+        xml.text("published", &timestamp(published))?;
+        xml.text("updated", &timestamp(published))?;
+        xml.empty("link", &[("rel", "alternate"), ("href", &item.link)])?;
+        if let Some(creator) = item.dc_creator.as_ref().or(item.author.as_ref()) {
+            xml.start("author", &[])?;
+            xml.text("name", creator)?;
+            xml.end("author")?;
+        }
+        for category in &item.categories {
+            xml.empty("category", &[("term", category)])?;
+        }
+        if let Some(enclosure) = &item.enclosure {
+            xml.empty(
+                "link",
+                &[
+                    ("rel", "enclosure"),
+                    ("href", &enclosure.url),
+                    ("type", &enclosure.mime_type),
+                    ("length", &enclosure.length.to_string()),
+                ],
+            )?;
+        }
+        xml.text("summary", &item.description)?;
+        xml.end("entry")?;
+    }
+
+    xml.end("feed")?;
+    xml.flush()
+}
+
+fn timestamp(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::write::{Channel as WriteChannel, Item};
+
+    #[test]
+    fn test_from_rss() {
+        let rss = WriteChannel::new("Feed & Co", "https://example.com", "Books")
+            .generator("VBT")
+            .item(
+                Item::new("Tập 1", "https://example.com/1", "ISBN: 1", "id-1")
+                    .dc_creator("Translator")
+                    .enclosure("https://example.com/1.png", 42, "image/png"),
+            )
+            .build()
+            .unwrap();
+        let channel = Channel::parse(&rss).unwrap();
+
+        let atom = build(&channel, "https://example.com/feed.atom").unwrap();
+        assert!(atom.contains(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#));
+        assert!(atom.contains("<title>Feed &amp; Co</title>"));
+        assert!(atom.contains("<id>https://example.com/feed.atom</id>"));
+        assert!(atom.contains("<name>Translator</name>"));
+        assert!(atom.contains(
+            r#"<link rel="enclosure" href="https://example.com/1.png" type="image/png" length="42"/>"#
+        ));
+        assert!(atom.contains("<summary>ISBN: 1</summary>"));
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Tells apart the temporary files of concurrent writes in one process
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// Write a file through `write` into a temporary file next to `path`,
+/// fsync it, rename it into place and fsync the directory, so readers
+/// never see a partial file and a crash never loses the rename.
+/// The temporary file is removed if anything fails
+pub(crate) fn save_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    // Create parent directories if they don't exist
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+    if let Some(parent) = parent {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    // Unique per process and per write, two writers never share a file
+    let tmp_path = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = File::create_new(&tmp_path)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+
+    // Persist the rename itself. The new file is in place either way, so
+    // a failure here is only reported
+    #[cfg(unix)]
+    if let Err(e) = File::open(parent.unwrap_or(Path::new("."))).and_then(|dir| dir.sync_all()) {
+        eprintln!("Failed to sync the directory of {}: {}", path.display(), e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_atomic() {
+        let dir = std::env::temp_dir().join(format!("rss_fs_{}", std::process::id()));
+        let path = dir.join("nested/feed.xml");
+
+        save_atomic(&path, |writer| writer.write_all(b"first")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+
+        // A failed write keeps the old file and leaves no temporary file
+        let e = save_atomic(&path, |writer| {
+            writer.write_all(b"half")?;
+            Err(io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert_eq!(e.to_string(), "disk full");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+        assert_eq!(fs::read_dir(dir.join("nested")).unwrap().count(), 1);
+
+        // So does a rename that fails
+        let e = save_atomic(&dir.join("nested"), |writer| writer.write_all(b"x"));
+        assert!(e.is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
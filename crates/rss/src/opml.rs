@@ -0,0 +1,128 @@
+use crate::{fs::save_atomic, xml::XmlWriter};
+use chrono::{DateTime, Utc};
+use std::io::{self, Write};
+
+pub struct Opml {
+    title: String,
+    date_created: DateTime<Utc>,
+    outlines: Vec<Outline>,
+}
+
+pub struct Outline {
+    text: String,
+    xml_url: String,
+    html_url: Option<String>,
+    description: Option<String>,
+}
+
+impl Opml {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            date_created: Utc::now(),
+            outlines: Vec::new(),
+        }
+    }
+
+    pub fn date_created(mut self, date: DateTime<Utc>) -> Self {
+        self.date_created = date;
+        self
+    }
+
+    pub fn outline(mut self, outline: Outline) -> Self {
+        self.outlines.push(outline);
+        self
+    }
+
+    /// Add multiple outlines to the document
+    pub fn outlines(mut self, outlines: impl IntoIterator<Item = Outline>) -> Self {
+        self.outlines.extend(outlines);
+        self
+    }
+
+    pub fn build(&self) -> io::Result<String> {
+        let mut opml = Vec::with_capacity(self.outlines.len() * 300 + 300);
+        self.write_to(&mut opml)?;
+        String::from_utf8(opml).map_err(io::Error::other)
+    }
+
+    /// Stream the OPML document to a writer
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut xml = XmlWriter::new(writer, true);
+        xml.declaration()?;
+        xml.start("opml", &[("version", "2.0")])?;
+
+        xml.start("head", &[])?;
+        xml.text("title", &self.title)?;
+        xml.text("dateCreated", &self.date_created.to_rfc2822())?;
+        xml.end("head")?;
+
+        xml.start("body", &[])?;
+        for outline in &self.outlines {
+            let mut attrs = vec![
+                ("type", "rss"),
+                ("text", outline.text.as_str()),
+                ("title", outline.text.as_str()),
+                ("xmlUrl", outline.xml_url.as_str()),
+            ];
+            if let Some(html_url) = &outline.html_url {
+                attrs.push(("htmlUrl", html_url));
+            }
+            if let Some(description) = &outline.description {
+                attrs.push(("description", description));
+            }
+            xml.empty("outline", &attrs)?;
+        }
+        xml.end("body")?;
+
+        xml.end("opml")?;
+        xml.flush()
+    }
+
+    /// Save the OPML to a file, replacing it atomically
+    pub fn save_to_file(&self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        save_atomic(path.as_ref(), |writer| self.write_to(writer))
+    }
+}
+
+impl Outline {
+    pub fn new(text: impl Into<String>, xml_url: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            xml_url: xml_url.into(),
+            html_url: None,
+            description: None,
+        }
+    }
+
+    pub fn html_url(mut self, url: impl Into<String>) -> Self {
+        self.html_url = Some(url.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_opml() {
+        let opml = Opml::new("Feeds")
+            .outline(
+                Outline::new("Feed & Co", "https://example.com/feed.rss")
+                    .description("Alternative Titles: A, B"),
+            )
+            .build()
+            .unwrap();
+
+        assert!(opml.contains("<title>Feeds</title>"));
+        assert!(opml.contains(r#"text="Feed &amp; Co""#));
+        assert!(opml.contains(r#"xmlUrl="https://example.com/feed.rss""#));
+        assert!(opml.contains(r#"description="Alternative Titles: A, B""#));
+    }
+}
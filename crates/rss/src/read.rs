@@ -0,0 +1,460 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use quick_xml::{
+    escape::resolve_predefined_entity,
+    events::{BytesStart, Event},
+    Reader,
+};
+use std::{fmt, path::Path};
+
+/// A feed parsed back from RSS 2.0 or Atom.
+/// Fields that are missing in the document are left empty
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Channel {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub language: Option<String>,
+    pub generator: Option<String>,
+    pub ttl: Option<u32>,
+    pub atom_link: Option<String>,
+    pub categories: Vec<String>,
+    pub image: Option<Image>,
+    pub pub_date: Option<DateTime<Utc>>,
+    pub last_build_date: Option<DateTime<Utc>>,
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Image {
+    pub url: String,
+    pub title: String,
+    pub link: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Item {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub author: Option<String>,
+    pub dc_creator: Option<String>,
+    pub dc_identifier: Option<String>,
+    pub categories: Vec<String>,
+    pub comments: Option<String>,
+    pub content_encoded: Option<String>,
+    pub enclosure: Option<Enclosure>,
+    pub guid: String,
+    pub pub_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Enclosure {
+    pub url: String,
+    pub length: u64,
+    pub mime_type: String,
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(std::io::Error),
+    Xml(quick_xml::Error),
+    /// The root element is neither `<rss>` nor `<feed>`
+    NotAFeed,
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "failed to read feed: {}", e),
+            ReadError::Xml(e) => write!(f, "invalid XML: {}", e),
+            ReadError::NotAFeed => write!(f, "document is not an RSS or Atom feed"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<std::io::Error> for ReadError {
+    fn from(e: std::io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl From<quick_xml::Error> for ReadError {
+    fn from(e: quick_xml::Error) -> Self {
+        ReadError::Xml(e)
+    }
+}
+
+#[derive(PartialEq)]
+enum Format {
+    Unknown,
+    Rss,
+    Atom,
+}
+
+#[derive(Default)]
+struct Parser {
+    channel: Channel,
+    item: Option<Item>,
+    image: Option<Image>,
+}
+
+impl Channel {
+    /// Parse an RSS 2.0 or Atom document
+    pub fn parse(xml: &str) -> Result<Channel, ReadError> {
+        // Older versions of the writer left item dates as `</pubDate` without `>`
+        let xml = xml.replace("</pubDate\n", "</pubDate>\n");
+        let mut reader = Reader::from_str(&xml);
+        let mut format = Format::Unknown;
+        let mut parser = Parser::default();
+        let mut stack: Vec<String> = Vec::new();
+        let mut text = String::new();
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(e) => {
+                    let name = qualified_name(&e);
+                    if stack.is_empty() {
+                        format = root_format(&name)?;
+                    }
+                    parser.open(&name, &e, &format)?;
+                    stack.push(name);
+                    text.clear();
+                }
+                Event::Empty(e) => {
+                    let name = qualified_name(&e);
+                    if stack.is_empty() {
+                        format = root_format(&name)?;
+                    }
+                    parser.open(&name, &e, &format)?;
+                    stack.push(name);
+                    parser.close(&stack, "", &format);
+                    stack.pop();
+                }
+                Event::End(_) => {
+                    parser.close(&stack, text.trim(), &format);
+                    stack.pop();
+                    text.clear();
+                }
+                Event::Text(e) => text.push_str(&e.xml_content().map_err(quick_xml::Error::from)?),
+                Event::CData(e) => text.push_str(&e.decode().map_err(quick_xml::Error::from)?),
+                Event::GeneralRef(e) => {
+                    if let Some(c) = e.resolve_char_ref()? {
+                        text.push(c);
+                    } else {
+                        let entity = e.decode().map_err(quick_xml::Error::from)?;
+                        match resolve_predefined_entity(&entity) {
+                            Some(resolved) => text.push_str(resolved),
+                            None => {
+                                text.push('&');
+                                text.push_str(&entity);
+                                text.push(';');
+                            }
+                        }
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if format == Format::Unknown {
+            return Err(ReadError::NotAFeed);
+        }
+
+        Ok(parser.channel)
+    }
+
+    /// Read and parse a feed file
+    pub fn read_from_file(path: impl AsRef<Path>) -> Result<Channel, ReadError> {
+        let xml = std::fs::read_to_string(path)?;
+        Channel::parse(&xml)
+    }
+}
+
+impl Parser {
+    fn open(&mut self, name: &str, e: &BytesStart, format: &Format) -> Result<(), ReadError> {
+        match (name, format) {
+            ("item", Format::Rss) | ("entry", Format::Atom) => self.item = Some(Item::default()),
+            ("image", Format::Rss) if self.item.is_none() => self.image = Some(Image::default()),
+            ("enclosure", Format::Rss) => {
+                if let Some(item) = &mut self.item {
+                    item.enclosure = Some(Enclosure {
+                        url: attribute(e, "url")?.unwrap_or_default(),
+                        length: attribute(e, "length")?
+                            .and_then(|length| length.parse().ok())
+                            .unwrap_or(0),
+                        mime_type: attribute(e, "type")?.unwrap_or_default(),
+                    });
+                }
+            }
+            ("atom:link", Format::Rss) if attribute(e, "rel")?.as_deref() == Some("self") => {
+                self.channel.atom_link = attribute(e, "href")?;
+            }
+            ("link", Format::Atom) => {
+                let href = attribute(e, "href")?.unwrap_or_default();
+                match attribute(e, "rel")?.as_deref() {
+                    None | Some("alternate") => match &mut self.item {
+                        Some(item) => item.link = href,
+                        None => self.channel.link = href,
+                    },
+                    Some("self") if self.item.is_none() => self.channel.atom_link = Some(href),
+                    Some("enclosure") => {
+                        if let Some(item) = &mut self.item {
+                            item.enclosure = Some(Enclosure {
+                                url: href,
+                                length: attribute(e, "length")?
+                                    .and_then(|length| length.parse().ok())
+                                    .unwrap_or(0),
+                                mime_type: attribute(e, "type")?.unwrap_or_default(),
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            ("category", Format::Atom) => {
+                if let Some(term) = attribute(e, "term")? {
+                    match &mut self.item {
+                        Some(item) => item.categories.push(term),
+                        None => self.channel.categories.push(term),
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, stack: &[String], text: &str, format: &Format) {
+        let Some(name) = stack.last().map(String::as_str) else {
+            return;
+        };
+        let parent = stack
+            .len()
+            .checked_sub(2)
+            .map(|i| stack[i].as_str())
+            .unwrap_or_default();
+
+        match format {
+            Format::Rss => self.close_rss(name, text),
+            Format::Atom => self.close_atom(name, parent, text),
+            Format::Unknown => {}
+        }
+    }
+
+    fn close_rss(&mut self, name: &str, text: &str) {
+        if name == "item" {
+            if let Some(item) = self.item.take() {
+                self.channel.items.push(item);
+            }
+            return;
+        }
+        if name == "image" && self.item.is_none() {
+            self.channel.image = self.image.take();
+            return;
+        }
+
+        if let Some(image) = &mut self.image {
+            match name {
+                "url" => image.url = text.to_string(),
+                "title" => image.title = text.to_string(),
+                "link" => image.link = text.to_string(),
+                _ => {}
+            }
+        } else if let Some(item) = &mut self.item {
+            match name {
+                "title" => item.title = text.to_string(),
+                "link" => item.link = text.to_string(),
+                "description" => item.description = text.to_string(),
+                "author" => item.author = Some(text.to_string()),
+                "dc:creator" => item.dc_creator = Some(text.to_string()),
+                "dc:identifier" => item.dc_identifier = Some(text.to_string()),
+                "category" => item.categories.push(text.to_string()),
+                "comments" => item.comments = Some(text.to_string()),
+                "content:encoded" => item.content_encoded = Some(text.to_string()),
+                "guid" => item.guid = text.to_string(),
+                "pubDate" | "dc:date" => item.pub_date = parse_date(text),
+                _ => {}
+            }
+        } else {
+            let channel = &mut self.channel;
+            match name {
+                "title" => channel.title = text.to_string(),
+                "link" => channel.link = text.to_string(),
+                "description" => channel.description = text.to_string(),
+                "language" => channel.language = Some(text.to_string()),
+                "generator" => channel.generator = Some(text.to_string()),
+                "ttl" => channel.ttl = text.parse().ok(),
+                "category" => channel.categories.push(text.to_string()),
+                "pubDate" => channel.pub_date = parse_date(text),
+                "lastBuildDate" => channel.last_build_date = parse_date(text),
+                _ => {}
+            }
+        }
+    }
+
+    fn close_atom(&mut self, name: &str, parent: &str, text: &str) {
+        if name == "entry" {
+            if let Some(item) = self.item.take() {
+                self.channel.items.push(item);
+            }
+            return;
+        }
+
+        if let Some(item) = &mut self.item {
+            match (name, parent) {
+                ("title", _) => item.title = text.to_string(),
+                ("summary", _) => item.description = text.to_string(),
+                ("content", _) => item.content_encoded = Some(text.to_string()),
+                ("id", _) => item.guid = text.to_string(),
+                ("name", "author") => item.dc_creator = Some(text.to_string()),
+                ("email", "author") => item.author = Some(text.to_string()),
+                ("published", _) => item.pub_date = parse_date(text),
+                ("updated", _) if item.pub_date.is_none() => item.pub_date = parse_date(text),
+                _ => {}
+            }
+        } else {
+            let channel = &mut self.channel;
+            match name {
+                "title" => channel.title = text.to_string(),
+                "subtitle" => channel.description = text.to_string(),
+                "generator" => channel.generator = Some(text.to_string()),
+                "updated" => channel.last_build_date = parse_date(text),
+                "logo" | "icon" if channel.image.is_none() => {
+                    channel.image = Some(Image {
+                        url: text.to_string(),
+                        title: channel.title.clone(),
+                        link: channel.link.clone(),
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn qualified_name(e: &BytesStart) -> String {
+    String::from_utf8_lossy(e.name().as_ref()).into_owned()
+}
+
+fn root_format(name: &str) -> Result<Format, ReadError> {
+    match name {
+        "rss" => Ok(Format::Rss),
+        "feed" => Ok(Format::Atom),
+        _ => Err(ReadError::NotAFeed),
+    }
+}
+
+fn attribute(e: &BytesStart, key: &str) -> Result<Option<String>, ReadError> {
+    match e.try_get_attribute(key).map_err(quick_xml::Error::from)? {
+        Some(attr) => Ok(Some(attr.unescape_value()?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Parse RFC 2822 (RSS), RFC 3339 (Atom) and the `2024-01-01 00:00:00 UTC`
+/// format older versions of the writer used for item dates
+fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(s)
+        .or_else(|_| DateTime::parse_from_rfc3339(s))
+        .map(|d| d.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S UTC")
+                .ok()
+                .map(|d| d.and_utc())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rss() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/">
+<channel>
+     <title>Dược sư tự sự</title>
+     <link>https://example.com</link>
+     <description>A &amp; B</description>
+     <image>
+         <url>https://example.com/cover.jpg</url>
+         <title>Cover</title>
+         <link>https://example.com</link>
+     </image>
+     <lastBuildDate>Sat, 11 Oct 2025 02:56:26 +0000</lastBuildDate>
+     <item>
+         <title>Tập 14</title>
+         <link>https://example.com/item</link>
+         <description>ISBN: 978-604-2-25190-7</description>
+         <dc:creator>Thảo Aki</dc:creator>
+     <enclosure url="https://example.com/cover.jpg" length="0" type="image/jpeg"></enclosure>
+     <guid isPermaLink="false">https://example.com/?query=T%E1%BA%ADp+14</guid>
+     <pubDate>2025-10-11 02:56:26 UTC</pubDate>
+     </item>
+</channel>
+</rss>"#;
+
+        let channel = Channel::parse(xml).unwrap();
+        assert_eq!(channel.title, "Dược sư tự sự");
+        assert_eq!(channel.description, "A & B");
+        assert_eq!(channel.image.unwrap().url, "https://example.com/cover.jpg");
+        assert!(channel.last_build_date.is_some());
+
+        let item = &channel.items[0];
+        assert_eq!(item.title, "Tập 14");
+        assert_eq!(item.dc_creator.as_deref(), Some("Thảo Aki"));
+        assert_eq!(item.enclosure.as_ref().unwrap().mime_type, "image/jpeg");
+        assert_eq!(
+            item.pub_date.unwrap().to_rfc2822(),
+            "Sat, 11 Oct 2025 02:56:26 +0000"
+        );
+    }
+
+    #[test]
+    fn test_parse_atom() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example</title>
+  <link href="https://example.com/"/>
+  <link rel="self" href="https://example.com/feed.atom"/>
+  <updated>2025-10-11T02:56:26Z</updated>
+  <entry>
+    <title>Entry</title>
+    <link href="https://example.com/entry"/>
+    <id>urn:uuid:1</id>
+    <author><name>Someone</name></author>
+    <category term="News"/>
+    <published>2025-10-10T00:00:00+07:00</published>
+    <summary>Summary</summary>
+  </entry>
+</feed>"#;
+
+        let channel = Channel::parse(xml).unwrap();
+        assert_eq!(channel.link, "https://example.com/");
+        assert_eq!(
+            channel.atom_link.as_deref(),
+            Some("https://example.com/feed.atom")
+        );
+
+        let item = &channel.items[0];
+        assert_eq!(item.guid, "urn:uuid:1");
+        assert_eq!(item.dc_creator.as_deref(), Some("Someone"));
+        assert_eq!(item.categories, vec!["News".to_string()]);
+        assert_eq!(
+            item.pub_date.unwrap().to_rfc2822(),
+            "Thu, 9 Oct 2025 17:00:00 +0000"
+        );
+    }
+
+    #[test]
+    fn test_not_a_feed() {
+        assert!(matches!(
+            Channel::parse("<html></html>"),
+            Err(ReadError::NotAFeed)
+        ));
+    }
+}
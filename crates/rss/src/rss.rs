@@ -1 +1,6 @@
+pub mod atom;
+mod fs;
+pub mod opml;
+pub mod read;
 pub mod write;
+mod xml;
@@ -1,14 +1,25 @@
+use crate::{fs::save_atomic, xml::XmlWriter};
 use chrono::{DateTime, Utc};
-use std::fmt::Write;
+use std::io::{self, Write};
+
+pub const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+pub const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+pub const CONTENT_NS: &str = "http://purl.org/rss/1.0/modules/content/";
 
 pub struct Channel {
     title: String,
     link: String,
     description: String,
+    language: Option<String>,
+    generator: Option<String>,
+    ttl: Option<u32>,
+    atom_link: Option<String>,
+    categories: Vec<String>,
     image: Option<Image>,
     pub_date: DateTime<Utc>,
     last_build_date: DateTime<Utc>,
     items: Vec<Item>,
+    pretty: bool,
 }
 
 pub struct Image {
@@ -21,6 +32,12 @@ pub struct Item {
     title: String,
     link: String,
     description: String,
+    author: Option<String>,
+    dc_creator: Option<String>,
+    dc_identifier: Option<String>,
+    categories: Vec<String>,
+    comments: Option<String>,
+    content_encoded: Option<String>,
     enclosure: Option<Enclosure>,
     guid: String,
     pub_date: DateTime<Utc>,
@@ -43,10 +60,16 @@ impl Channel {
             title: title.into(),
             link: link.into(),
             description: description.into(),
+            language: None,
+            generator: None,
+            ttl: None,
+            atom_link: None,
+            categories: Vec::new(),
             image: None,
             pub_date: now,
             last_build_date: now,
             items: Vec::new(),
+            pretty: true,
         }
     }
 
@@ -64,6 +87,34 @@ impl Channel {
         self
     }
 
+    /// Language code of the channel, e.g. "vi"
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    pub fn generator(mut self, generator: impl Into<String>) -> Self {
+        self.generator = Some(generator.into());
+        self
+    }
+
+    /// How many minutes the channel can be cached before refreshing
+    pub fn ttl(mut self, minutes: u32) -> Self {
+        self.ttl = Some(minutes);
+        self
+    }
+
+    /// Url the feed itself is published at, written as `<atom:link rel="self">`
+    pub fn atom_link(mut self, url: impl Into<String>) -> Self {
+        self.atom_link = Some(url.into());
+        self
+    }
+
+    pub fn category(mut self, category: impl Into<String>) -> Self {
+        self.categories.push(category.into());
+        self
+    }
+
     pub fn pub_date(mut self, date: DateTime<Utc>) -> Self {
         self.pub_date = date;
         self
@@ -85,98 +136,90 @@ impl Channel {
         self
     }
 
-    pub fn build(self) -> Result<String, std::fmt::Error> {
-        let mut rss = String::with_capacity(self.items.len() * 500 + 500);
-
-        writeln!(rss, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
-        writeln!(rss, r#"<rss version="2.0">"#)?;
-        writeln!(rss, "<channel>")?;
-        writeln!(rss, "     <title>{}</title>", escape_xml(&self.title))?;
-        writeln!(rss, "     <link>{}</link>", escape_xml(&self.link))?;
-        writeln!(
-            rss,
-            "     <description>{}</description>",
-            escape_xml(&self.description)
-        )?;
+    /// Indent the output (default) or write everything on one line
+    pub fn pretty(mut self, pretty: bool) -> Self {
+        self.pretty = pretty;
+        self
+    }
 
-        if let Some(image) = &self.image {
-            writeln!(rss, "     <image>")?;
-            writeln!(rss, "         <url>{}</url>", escape_xml(&image.url))?;
-            writeln!(rss, "         <title>{}</title>", escape_xml(&image.title))?;
-            writeln!(rss, "         <link>{}</link>", escape_xml(&image.link))?;
-            writeln!(rss, "     </image>")?;
-        }
-
-        writeln!(
-            rss,
-            "     <pubDate>{}</pubDate>",
-            self.pub_date.to_rfc2822()
-        )?;
-        writeln!(
-            rss,
-            "     <lastBuildDate>{}</lastBuildDate>",
-            self.last_build_date.to_rfc2822()
-        )?;
+    pub fn build(&self) -> io::Result<String> {
+        let mut rss = Vec::with_capacity(self.items.len() * 500 + 500);
+        self.write_to(&mut rss)?;
+        String::from_utf8(rss).map_err(io::Error::other)
+    }
 
-        for item in &self.items {
-            writeln!(rss, "     <item>")?;
-            writeln!(rss, "         <title>{}</title>", escape_xml(&item.title))?;
-            writeln!(rss, "         <link>{}</link>", escape_xml(&item.link))?;
-            writeln!(
-                rss,
-                "         <description>{}</description>",
-                escape_xml(&item.description)
-            )?;
+    /// Stream the RSS document to a writer
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut xml = XmlWriter::new(writer, self.pretty);
+        xml.declaration()?;
 
-            if let Some(enclosure) = &item.enclosure {
-                writeln!(
-                    rss,
-                    r#"     <enclosure url="{}" length="{}" type="{}"></enclosure>"#,
-                    escape_xml(&enclosure.url),
-                    enclosure.length,
-                    escape_xml(&enclosure.mime_type)
-                )?;
-            }
-            writeln!(
-                rss,
-                r#"     <guid isPermaLink="false">{}</guid>"#,
-                escape_xml(&item.guid)
-            )?;
-            writeln!(
-                rss,
-                "     <pubDate>{}</pubDate>",
-                escape_xml(&item.pub_date)
-            )?;
-            writeln!(rss, "     </item>")?;
+        let mut rss_attrs = vec![("version", "2.0")];
+        if self.atom_link.is_some() {
+            rss_attrs.push(("xmlns:atom", ATOM_NS));
+        }
+        if self
+            .items
+            .iter()
+            .any(|item| item.dc_creator.is_some() || item.dc_identifier.is_some())
+        {
+            rss_attrs.push(("xmlns:dc", DC_NS));
         }
+        if self.items.iter().any(|item| item.content_encoded.is_some()) {
+            rss_attrs.push(("xmlns:content", CONTENT_NS));
+        }
+        xml.start("rss", &rss_attrs)?;
+        xml.start("channel", &[])?;
 
-        writeln!(rss, "</channel>")?;
-        writeln!(rss, "</rss>")?;
+        xml.text("title", &self.title)?;
+        xml.text("link", &self.link)?;
+        xml.text("description", &self.description)?;
 
-        Ok(rss)
-    }
+        if let Some(atom_link) = &self.atom_link {
+            xml.empty(
+                "atom:link",
+                &[
+                    ("href", atom_link),
+                    ("rel", "self"),
+                    ("type", "application/rss+xml"),
+                ],
+            )?;
+        }
+        if let Some(language) = &self.language {
+            xml.text("language", language)?;
+        }
+        if let Some(generator) = &self.generator {
+            xml.text("generator", generator)?;
+        }
+        if let Some(ttl) = self.ttl {
+            xml.text("ttl", &ttl.to_string())?;
+        }
+        for category in &self.categories {
+            xml.text("category", category)?;
+        }
 
-    /// Write rss to a writer
-    pub fn write_to<W: std::io::Write>(self, writer: &mut W) -> std::io::Result<()> {
-        let rss = self
-            .build()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        writer.write_all(rss.as_bytes())
-    }
+        if let Some(image) = &self.image {
+            xml.start("image", &[])?;
+            xml.text("url", &image.url)?;
+            xml.text("title", &image.title)?;
+            xml.text("link", &image.link)?;
+            xml.end("image")?;
+        }
 
-    /// Save the RSS to a file
-    pub fn save_to_file(self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
-        let path = path.as_ref();
+        xml.text("pubDate", &self.pub_date.to_rfc2822())?;
+        xml.text("lastBuildDate", &self.last_build_date.to_rfc2822())?;
 
-        // Create parent directories if they don't exist
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+        for item in &self.items {
+            item.write_to(&mut xml)?;
         }
 
-        let rss = self
-            .build()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        std::fs::write(path, rss)
+        xml.end("channel")?;
+        xml.end("rss")?;
+        xml.flush()
+    }
+
+    /// Save the RSS to a file, replacing it atomically
+    pub fn save_to_file(&self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        save_atomic(path.as_ref(), |writer| self.write_to(writer))
     }
 }
 
@@ -191,12 +234,55 @@ impl Item {
             title: title.into(),
             link: link.into(),
             description: description.into(),
+            author: None,
+            dc_creator: None,
+            dc_identifier: None,
+            categories: Vec::new(),
+            comments: None,
+            content_encoded: None,
             enclosure: None,
             guid: guid.into(),
             pub_date: Utc::now(),
         }
     }
 
+    /// Email address of the author, as required by RSS 2.0.
+    /// Use [`Item::dc_creator`] for a plain name
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    /// Name of the creator, written as `<dc:creator>`
+    pub fn dc_creator(mut self, creator: impl Into<String>) -> Self {
+        self.dc_creator = Some(creator.into());
+        self
+    }
+
+    /// Unambiguous reference to the item, such as `urn:isbn:...`, written
+    /// as `<dc:identifier>`
+    pub fn dc_identifier(mut self, identifier: impl Into<String>) -> Self {
+        self.dc_identifier = Some(identifier.into());
+        self
+    }
+
+    pub fn category(mut self, category: impl Into<String>) -> Self {
+        self.categories.push(category.into());
+        self
+    }
+
+    /// Url of a page with comments about the item
+    pub fn comments(mut self, url: impl Into<String>) -> Self {
+        self.comments = Some(url.into());
+        self
+    }
+
+    /// Full content of the item, written as CDATA in `<content:encoded>`
+    pub fn content_encoded(mut self, content: impl Into<String>) -> Self {
+        self.content_encoded = Some(content.into());
+        self
+    }
+
     pub fn enclosure(
         mut self,
         url: impl Into<String>,
@@ -214,20 +300,52 @@ impl Item {
         self.pub_date = date;
         self
     }
-}
 
-fn escape_xml<T: ToString>(s: T) -> String {
-    s.to_string()
-        .replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&apos;")
+    fn write_to<W: Write>(&self, xml: &mut XmlWriter<W>) -> io::Result<()> {
+        xml.start("item", &[])?;
+        xml.text("title", &self.title)?;
+        xml.text("link", &self.link)?;
+        xml.text("description", &self.description)?;
+
+        if let Some(author) = &self.author {
+            xml.text("author", author)?;
+        }
+        if let Some(dc_creator) = &self.dc_creator {
+            xml.text("dc:creator", dc_creator)?;
+        }
+        if let Some(dc_identifier) = &self.dc_identifier {
+            xml.text("dc:identifier", dc_identifier)?;
+        }
+        for category in &self.categories {
+            xml.text("category", category)?;
+        }
+        if let Some(comments) = &self.comments {
+            xml.text("comments", comments)?;
+        }
+        if let Some(content) = &self.content_encoded {
+            xml.cdata("content:encoded", content)?;
+        }
+        if let Some(enclosure) = &self.enclosure {
+            xml.empty(
+                "enclosure",
+                &[
+                    ("url", &enclosure.url),
+                    ("length", &enclosure.length.to_string()),
+                    ("type", &enclosure.mime_type),
+                ],
+            )?;
+        }
+
+        xml.text_with_attrs("guid", &[("isPermaLink", "false")], &self.guid)?;
+        xml.text("pubDate", &self.pub_date.to_rfc2822())?;
+        xml.end("item")
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{read, xml::escape_xml};
 
     #[test]
     fn test_basic_rss() {
@@ -244,9 +362,121 @@ mod tests {
         assert!(rss.contains("<title>Test Item</title>"));
     }
 
+    #[test]
+    fn test_extended_elements() {
+        let channel = Channel::new("Test Channel", "https://example.com", "A test channel")
+            .language("vi")
+            .ttl(60)
+            .atom_link("https://example.com/feed.rss")
+            .item(
+                Item::new("Test Item", "https://example.com/item", "Test", "id-1")
+                    .dc_creator("Translator")
+                    .category("Partner")
+                    .content_encoded("<p>Body ]]> end</p>"),
+            );
+
+        let rss = channel.build().unwrap();
+        assert!(rss.contains(r#"xmlns:atom="http://www.w3.org/2005/Atom""#));
+        assert!(rss.contains(r#"xmlns:dc="http://purl.org/dc/elements/1.1/""#));
+        assert!(rss.contains(r#"xmlns:content="http://purl.org/rss/1.0/modules/content/""#));
+        assert!(rss.contains(r#"<atom:link href="https://example.com/feed.rss" rel="self""#));
+        assert!(rss.contains("<language>vi</language>"));
+        assert!(rss.contains("<ttl>60</ttl>"));
+        assert!(rss.contains("<dc:creator>Translator</dc:creator>"));
+        assert!(rss.contains("<category>Partner</category>"));
+        assert!(rss.contains("<![CDATA[<p>Body ]]]]><![CDATA[> end</p>]]>"));
+    }
+
     #[test]
     fn test_xml_escaping() {
         let result = escape_xml("Test & <test> \"quoted\"");
         assert_eq!(result, "Test &amp; &lt;test&gt; &quot;quoted&quot;");
     }
+
+    #[test]
+    fn test_invalid_chars_are_stripped() {
+        let result = escape_xml("Tập\u{1} 1\u{b}\u{fffe} & 'x'");
+        assert_eq!(result, "Tập 1 &amp; &apos;x&apos;");
+    }
+
+    fn sample_channel() -> Channel {
+        let date = DateTime::parse_from_rfc2822("Sat, 11 Oct 2025 02:56:26 +0000")
+            .unwrap()
+            .with_timezone(&Utc);
+        Channel::new(
+            "Dược sư tự sự <&>",
+            "https://example.com",
+            "\"quoted\" 'feed'\u{7}",
+        )
+        .language("vi")
+        .generator("VBT")
+        .ttl(1440)
+        .atom_link("https://example.com/feed.rss?a=1&b=2")
+        .category("Light novel")
+        .image(
+            "https://example.com/cover.jpg",
+            "Cover",
+            "https://example.com",
+        )
+        .pub_date(date)
+        .last_build_date(date)
+        .item(
+            Item::new(
+                "Tập 14 (Bản đặc biệt)",
+                "https://example.com/item",
+                "Author: A & B | ISBN: 978-604-2-25191-4",
+                "https://example.com/?query=T%E1%BA%ADp+14",
+            )
+            .dc_creator("Thảo Aki")
+            .dc_identifier("urn:isbn:978-604-2-25191-4")
+            .category("Partner")
+            .comments("https://example.com/comments")
+            .content_encoded("<p>]]></p>\u{0}")
+            .enclosure("https://example.com/cover.jpg", 1234, "image/jpeg")
+            .pub_date(date),
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let channel = sample_channel();
+        let pretty = channel.build().unwrap();
+        let parsed = read::Channel::parse(&pretty).unwrap();
+
+        assert_eq!(parsed.title, "Dược sư tự sự <&>");
+        assert_eq!(parsed.description, "\"quoted\" 'feed'");
+        assert_eq!(parsed.language.as_deref(), Some("vi"));
+        assert_eq!(parsed.ttl, Some(1440));
+        assert_eq!(
+            parsed.atom_link.as_deref(),
+            Some("https://example.com/feed.rss?a=1&b=2")
+        );
+        assert_eq!(parsed.pub_date, Some(channel.pub_date));
+        assert_eq!(parsed.image.as_ref().unwrap().title, "Cover");
+
+        let item = &parsed.items[0];
+        assert_eq!(item.description, "Author: A & B | ISBN: 978-604-2-25191-4");
+        assert_eq!(item.dc_creator.as_deref(), Some("Thảo Aki"));
+        assert_eq!(
+            item.dc_identifier.as_deref(),
+            Some("urn:isbn:978-604-2-25191-4")
+        );
+        assert_eq!(item.content_encoded.as_deref(), Some("<p>]]></p>"));
+        assert_eq!(item.enclosure.as_ref().unwrap().length, 1234);
+        assert_eq!(item.pub_date, Some(channel.pub_date));
+
+        let compact = channel.pretty(false).build().unwrap();
+        assert_eq!(compact.lines().count(), 1);
+        assert_eq!(read::Channel::parse(&compact).unwrap(), parsed);
+    }
+
+    #[test]
+    fn test_consistent_indentation() {
+        let rss = sample_channel().build().unwrap();
+        assert!(rss.contains("\n        <item>\n            <title>"));
+        assert!(rss.contains("\n            <enclosure url="));
+        assert!(rss.contains(
+            "\n            <pubDate>Sat, 11 Oct 2025 02:56:26 +0000</pubDate>\n        </item>"
+        ));
+    }
 }
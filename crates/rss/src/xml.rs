@@ -0,0 +1,165 @@
+use std::io::{self, Write};
+
+const INDENT: &[u8] = b"    ";
+
+/// Minimal streaming XML writer, every element is written straight to `W`
+pub(crate) struct XmlWriter<W: Write> {
+    writer: W,
+    pretty: bool,
+    depth: usize,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub(crate) fn new(writer: W, pretty: bool) -> Self {
+        Self {
+            writer,
+            pretty,
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn declaration(&mut self) -> io::Result<()> {
+        self.writer
+            .write_all(br#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        self.newline()
+    }
+
+    /// `<name attr="value">`
+    pub(crate) fn start(&mut self, name: &str, attrs: &[(&str, &str)]) -> io::Result<()> {
+        self.open_tag(name, attrs)?;
+        self.writer.write_all(b">")?;
+        self.newline()?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// `</name>`
+    pub(crate) fn end(&mut self, name: &str) -> io::Result<()> {
+        self.depth = self.depth.saturating_sub(1);
+        self.indent()?;
+        write!(self.writer, "</{}>", name)?;
+        self.newline()
+    }
+
+    /// `<name attr="value"/>`
+    pub(crate) fn empty(&mut self, name: &str, attrs: &[(&str, &str)]) -> io::Result<()> {
+        self.open_tag(name, attrs)?;
+        self.writer.write_all(b"/>")?;
+        self.newline()
+    }
+
+    /// `<name>text</name>`
+    pub(crate) fn text(&mut self, name: &str, text: &str) -> io::Result<()> {
+        self.text_with_attrs(name, &[], text)
+    }
+
+    /// `<name attr="value">text</name>`
+    pub(crate) fn text_with_attrs(
+        &mut self,
+        name: &str,
+        attrs: &[(&str, &str)],
+        text: &str,
+    ) -> io::Result<()> {
+        self.open_tag(name, attrs)?;
+        self.writer.write_all(b">")?;
+        write_escaped(&mut self.writer, text)?;
+        write!(self.writer, "</{}>", name)?;
+        self.newline()
+    }
+
+    /// `<name><![CDATA[text]]></name>`, any `]]>` in `text` is split
+    pub(crate) fn cdata(&mut self, name: &str, text: &str) -> io::Result<()> {
+        self.open_tag(name, &[])?;
+        self.writer.write_all(b"><![CDATA[")?;
+        let mut sections = text.split("]]>").peekable();
+        while let Some(section) = sections.next() {
+            write_valid(&mut self.writer, section)?;
+            if sections.peek().is_some() {
+                self.writer.write_all(b"]]]]><![CDATA[>")?;
+            }
+        }
+        write!(self.writer, "]]></{}>", name)?;
+        self.newline()
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn open_tag(&mut self, name: &str, attrs: &[(&str, &str)]) -> io::Result<()> {
+        self.indent()?;
+        write!(self.writer, "<{}", name)?;
+        for (key, value) in attrs {
+            write!(self.writer, r#" {}=""#, key)?;
+            write_escaped(&mut self.writer, value)?;
+            self.writer.write_all(b"\"")?;
+        }
+        Ok(())
+    }
+
+    fn indent(&mut self) -> io::Result<()> {
+        if self.pretty {
+            for _ in 0..self.depth {
+                self.writer.write_all(INDENT)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn newline(&mut self) -> io::Result<()> {
+        if self.pretty {
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Characters allowed by the XML 1.0 `Char` production
+fn is_valid_xml_char(c: char) -> bool {
+    matches!(c,
+        '\u{9}' | '\u{A}' | '\u{D}'
+        | '\u{20}'..='\u{D7FF}'
+        | '\u{E000}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{10FFFF}')
+}
+
+/// Write `s` escaped for text and attribute values in a single pass,
+/// dropping code points that are not allowed in XML 1.0
+pub(crate) fn write_escaped<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let replacement = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            '"' => "&quot;",
+            '\'' => "&apos;",
+            c if !is_valid_xml_char(c) => "",
+            _ => continue,
+        };
+        writer.write_all(&s.as_bytes()[start..i])?;
+        writer.write_all(replacement.as_bytes())?;
+        start = i + c.len_utf8();
+    }
+    writer.write_all(&s.as_bytes()[start..])
+}
+
+/// Write `s` unescaped, only dropping invalid code points (used inside CDATA)
+fn write_valid<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if !is_valid_xml_char(c) {
+            writer.write_all(&s.as_bytes()[start..i])?;
+            start = i + c.len_utf8();
+        }
+    }
+    writer.write_all(&s.as_bytes()[start..])
+}
+
+#[cfg(test)]
+pub(crate) fn escape_xml<T: ToString>(s: T) -> String {
+    let mut escaped = Vec::new();
+    // Writing into a Vec can't fail
+    let _ = write_escaped(&mut escaped, &s.to_string());
+    String::from_utf8(escaped).unwrap_or_default()
+}
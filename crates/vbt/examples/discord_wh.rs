@@ -1,10 +1,17 @@
 use dotenv::dotenv;
-use vbt_lib::discord::{send::*, wh};
+use vbt_lib::{
+    config::{app::AppConfig, notify::NotifyConfig},
+    context::AppContext,
+    discord::{send::*, wh},
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
-    let url = wh::processed_url()?;
+    let config = AppConfig::load(None, &[])?;
+    let notify = NotifyConfig::load(&config.notify.file)?;
+    let ctx = AppContext::new(config, notify);
+    let url = wh::processed_url(&ctx)?;
     let title = "Embed Title".to_string();
     let description = "This is a description of the embed message.".to_string();
 
@@ -33,6 +40,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ..Default::default()
     };
 
-    send(url, "Content test".to_string(), Some(embed)).await?;
+    send(&ctx.client, url, "Content test".to_string(), Some(embed)).await?;
     Ok(())
 }
@@ -0,0 +1,150 @@
+use clap::{Args, Parser, Subcommand};
+
+/// Vietnamese Books Feed Tracker
+#[derive(Parser, Debug)]
+#[command(name = "vbt", version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalOpts,
+
+    /// Defaults to `run`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Accepted by every subcommand
+#[derive(Args, Debug, Clone)]
+pub struct GlobalOpts {
+    /// Show what would change without writing files or sending notifications
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Print more details about each step
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    /// Config file, vbt.toml in the working directory by default
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Override a config setting, e.g. `--set fetch.concurrency=4`
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Fetch every watchlist entry, update the feeds and notify
+    Run {
+        /// Only process the entry with this name or feed name
+        #[arg(long, value_name = "NAME")]
+        only: Option<String>,
+    },
+    /// Search the registry and print the rows
+    Search {
+        query: String,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Try every title of a watchlist entry against the registry and save
+    /// the best ones as its queries
+    Discover {
+        /// Name, romaji, english or feed name of the entry
+        title: String,
+        /// Rows shown per title
+        #[arg(long, default_value_t = 3)]
+        sample: usize,
+    },
+    /// Add an entry to the watchlist
+    Add(AddArgs),
+    /// Remove an entry from the watchlist by name or feed name, with its feed and JSON
+    Remove { name: String },
+    /// Regenerate the RSS feeds and OPML from feed/json without fetching
+    RebuildFeeds,
+    /// Look at or clean up the date cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Send a sample notification through every enabled backend
+    NotifyTest,
+    /// Serve the feeds and a JSON API over HTTP, refreshing them on a timer
+    Serve,
+    /// Keep running and check the entries that are due on `daemon.schedule`
+    Daemon,
+}
+
+#[derive(Args, Debug)]
+pub struct AddArgs {
+    /// Title searched in the registry
+    pub name: String,
+    /// Cover image url
+    #[arg(long, default_value = "")]
+    pub cover: String,
+    #[arg(long)]
+    pub native: Option<String>,
+    /// Also used as the feed file name
+    #[arg(long)]
+    pub romaji: Option<String>,
+    #[arg(long)]
+    pub english: Option<String>,
+    /// Can be repeated
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+    /// AniList media id, fills in missing titles and the cover on runs
+    #[arg(long, value_name = "ID")]
+    pub anilist: Option<u64>,
+    /// Hours between checks by `vbt daemon`, for series that release often
+    #[arg(long, value_name = "HOURS")]
+    pub interval_hours: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheAction {
+    /// Print how many dates are cached and which are no longer used
+    Inspect,
+    /// Drop dates of books and entries that are no longer in any feed
+    Prune,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_parse() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["vbt"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.global.config, None);
+
+        let cli = Cli::parse_from(["vbt", "run", "--only", "Kusuriya", "--dry-run"]);
+        assert!(cli.global.dry_run);
+        assert!(
+            matches!(cli.command, Some(Command::Run { only: Some(name) }) if name == "Kusuriya")
+        );
+
+        let cli = Cli::parse_from([
+            "vbt",
+            "cache",
+            "prune",
+            "-v",
+            "--config",
+            "other.toml",
+            "--set",
+            "paths.rss_dir=out",
+        ]);
+        assert!(cli.global.verbose);
+        assert_eq!(cli.global.config.as_deref(), Some("other.toml"));
+        assert_eq!(cli.global.overrides, ["paths.rss_dir=out"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Cache {
+                action: CacheAction::Prune
+            })
+        ));
+    }
+}
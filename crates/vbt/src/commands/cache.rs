@@ -0,0 +1,95 @@
+use super::rebuild::load_feed_json;
+use crate::{
+    cli::{CacheAction, GlobalOpts},
+    context::AppContext,
+    services::watchlist::load_watchlist,
+    utils::cache::{generate_cache_key, load_cache, save_cache, Cache},
+};
+use std::{collections::HashSet, error::Error};
+
+/// Keys of `cache` that no current feed uses any more
+struct Orphans {
+    books: Vec<String>,
+    titles: Vec<String>,
+}
+
+fn find_orphans(ctx: &AppContext, cache: &Cache<String>) -> Result<Orphans, String> {
+    let watchlist = load_watchlist(ctx)?;
+    let mut books = HashSet::new();
+    for entry in &watchlist {
+        if let Some(feed_json) = load_feed_json(ctx, &entry.slug())? {
+            books.extend(feed_json.rows.iter().map(generate_cache_key));
+        }
+    }
+    let names: HashSet<_> = watchlist.iter().map(|entry| entry.name.as_str()).collect();
+
+    Ok(Orphans {
+        books: cache
+            .data
+            .keys()
+            .filter(|key| !books.contains(*key))
+            .cloned()
+            .collect(),
+        titles: cache
+            .title_build_date
+            .keys()
+            .filter(|name| !names.contains(name.as_str()))
+            .cloned()
+            .collect(),
+    })
+}
+
+pub fn cache(
+    ctx: &AppContext,
+    opts: &GlobalOpts,
+    action: &CacheAction,
+) -> Result<(), Box<dyn Error>> {
+    let path = &ctx.path(&ctx.config.paths.date_cache);
+    let mut cache = load_cache::<String>(path)?;
+    let orphans = find_orphans(ctx, &cache)?;
+
+    match action {
+        CacheAction::Inspect => {
+            println!("{}:", path);
+            println!(
+                "  books: {} ({} unused)",
+                cache.data.len(),
+                orphans.books.len()
+            );
+            println!(
+                "  entries: {} ({} unused)",
+                cache.title_build_date.len(),
+                orphans.titles.len()
+            );
+            if opts.verbose {
+                for key in orphans.books.iter().chain(&orphans.titles) {
+                    println!("  unused: {}", key);
+                }
+            }
+        }
+        CacheAction::Prune => {
+            for key in &orphans.books {
+                cache.data.remove(key);
+            }
+            for name in &orphans.titles {
+                cache.title_build_date.remove(name);
+            }
+            if opts.verbose || opts.dry_run {
+                for key in orphans.books.iter().chain(&orphans.titles) {
+                    println!("Pruned: {}", key);
+                }
+            }
+            if !opts.dry_run && orphans.books.len() + orphans.titles.len() > 0 {
+                save_cache(&cache, path)?;
+            }
+            println!(
+                "Pruned {} book(s) and {} entry date(s){}",
+                orphans.books.len(),
+                orphans.titles.len(),
+                if opts.dry_run { " (dry run)" } else { "" }
+            );
+        }
+    }
+
+    Ok(())
+}
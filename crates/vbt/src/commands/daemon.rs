@@ -0,0 +1,212 @@
+use crate::{
+    cli::GlobalOpts,
+    commands::run::{self, RunFailed, Selection},
+    config::app::DaemonConfig,
+    context::AppContext,
+    file_ops::json_ops::read_json,
+    services::{run_report::EntryStatus, watchlist::load_watchlist},
+    types::watchlist::WatchlistEntry,
+    utils::{fs::write_atomic, schedule::Schedule},
+};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    future::Future,
+    path::Path,
+    time::Duration,
+};
+use tokio::sync::watch;
+
+/// An entry checked this much less than its interval ago is still due,
+/// so a wake up landing a little early doesn't skip it
+const SLACK_SECONDS: i64 = 5 * 60;
+
+/// Check the entries that are due on every wake up of `daemon.schedule`,
+/// until SIGTERM or Ctrl-C. A stop during a check finishes the entry being
+/// written and leaves the rest for the next start
+pub async fn daemon(ctx: &AppContext, opts: &GlobalOpts) -> Result<(), Box<dyn Error>> {
+    let config = &ctx.config.daemon;
+    let schedule = Schedule::parse(&config.schedule)?;
+    let tz = ctx.config.time.tz();
+    let mut state = load_state(&ctx.path(&config.state))?;
+
+    let (stopping, mut stop) = watch::channel(false);
+    let signal = shutdown_signal()?;
+    tokio::spawn(async move {
+        signal.await;
+        println!("Stopping once the current entry is written");
+        let _ = stopping.send(true);
+    });
+
+    // Restarts of several instances shouldn't all hit the registry at once
+    let jitter = fastrand::u64(0..=config.jitter_minutes * 60);
+    println!(
+        "Checking in {}s, then on {} ({})",
+        jitter, config.schedule, ctx.config.time.timezone
+    );
+    let mut wake = Utc::now() + Duration::from_secs(jitter);
+    while sleep_until(wake, &mut stop).await {
+        let now = Utc::now();
+        let next = next_wake(&schedule, tz, now)?;
+
+        if let Err(e) = check_due(ctx, opts, &mut state, now, next, &stop).await {
+            eprintln!("Check failed, retried at the next wake up: {}", e);
+        }
+
+        if *stop.borrow() {
+            break;
+        }
+        // A check that overran skips the wake ups it missed
+        wake = next.max(next_wake(&schedule, tz, Utc::now())?);
+    }
+
+    println!("Stopped");
+    Ok(())
+}
+
+/// Run the entries that are due at `now` and record the ones the registry
+/// answered for in `state`
+async fn check_due(
+    ctx: &AppContext,
+    opts: &GlobalOpts,
+    state: &mut BTreeMap<String, i64>,
+    now: DateTime<Utc>,
+    next: DateTime<Utc>,
+    stop: &watch::Receiver<bool>,
+) -> Result<(), Box<dyn Error>> {
+    let config = &ctx.config.daemon;
+    let watchlist = load_watchlist(ctx)?;
+    let due = due_entries(&watchlist, state, now.timestamp(), config);
+    if due.is_empty() {
+        if opts.verbose {
+            let tz = ctx.config.time.tz();
+            println!("Nothing due, next check at {}", next.with_timezone(&tz));
+        }
+        return Ok(());
+    }
+
+    println!("Checking {} of {} entries", due.len(), watchlist.len());
+    // The entries a failed run got through are recorded all the same
+    let (report, result) =
+        match run::run_selected(ctx, opts, Selection::Slugs(&due), next.timestamp(), stop).await {
+            Ok(report) => (report, Ok(())),
+            Err(RunFailed { report, error }) => (report, Err(error)),
+        };
+    // Entries that failed are retried at the next wake up
+    for entry in report.entries {
+        if entry.status != EntryStatus::Failed {
+            state.insert(entry.slug, now.timestamp());
+        }
+    }
+    let slugs: HashSet<_> = watchlist.iter().map(WatchlistEntry::slug).collect();
+    state.retain(|slug, _| slugs.contains(slug));
+    if !opts.dry_run {
+        save_state(&ctx.path(&config.state), state)?;
+    }
+    result
+}
+
+fn next_wake(schedule: &Schedule, tz: Tz, after: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    schedule
+        .next_after(&after.with_timezone(&tz))
+        .map(|next| next.with_timezone(&Utc))
+        .ok_or_else(|| "daemon.schedule has no upcoming time".to_string())
+}
+
+/// Sleep until `wake`, false if told to stop first
+async fn sleep_until(wake: DateTime<Utc>, stop: &mut watch::Receiver<bool>) -> bool {
+    let delay = (wake - Utc::now()).to_std().unwrap_or_default();
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => true,
+        _ = stop.wait_for(|stop| *stop) => false,
+    }
+}
+
+/// Resolves on SIGTERM or Ctrl-C. The handlers are installed right away,
+/// a signal arriving before the future is polled is not lost
+pub(crate) fn shutdown_signal() -> Result<impl Future<Output = ()>, String> {
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .map_err(|e| format!("Failed to listen for SIGTERM: {}", e))?;
+
+    Ok(async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+/// Feed names of the entries whose interval has passed since their last
+/// check, and of the ones never checked
+fn due_entries(
+    watchlist: &[WatchlistEntry],
+    state: &BTreeMap<String, i64>,
+    now: i64,
+    config: &DaemonConfig,
+) -> HashSet<String> {
+    watchlist
+        .iter()
+        .filter(|entry| {
+            let hours = entry.interval_hours.unwrap_or(config.entry_interval_hours);
+            state
+                .get(&entry.slug())
+                .is_none_or(|checked| now + SLACK_SECONDS >= checked + hours as i64 * 3600)
+        })
+        .map(WatchlistEntry::slug)
+        .collect()
+}
+
+/// Unix timestamp of the last check by feed name
+fn load_state(path: &str) -> Result<BTreeMap<String, i64>, String> {
+    if !Path::new(path).exists() {
+        return Ok(BTreeMap::new());
+    }
+    let data = read_json(path)?;
+    serde_json::from_str(&data).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+fn save_state(path: &str, state: &BTreeMap<String, i64>) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(state)
+        .map_err(|e| format!("Failed to serialize {}: {}", path, e))?;
+    write_atomic(path, data.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, interval_hours: Option<u64>) -> WatchlistEntry {
+        WatchlistEntry {
+            name: name.to_string(),
+            interval_hours,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_due_entries() {
+        let watchlist = [
+            entry("Hot", Some(6)),
+            entry("Daily", None),
+            entry("New", None),
+        ];
+        let config = DaemonConfig::default();
+        let now = 1_700_000_000;
+        let state = BTreeMap::from([
+            ("Hot".to_string(), now - 6 * 3600 + 60),
+            ("Daily".to_string(), now - 6 * 3600),
+        ]);
+
+        let due = due_entries(&watchlist, &state, now, &config);
+        assert_eq!(due, HashSet::from(["Hot".to_string(), "New".to_string()]));
+
+        let due = due_entries(&watchlist, &state, now + 18 * 3600, &config);
+        assert_eq!(due.len(), 3);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{
+    cli::GlobalOpts,
+    context::AppContext,
+    requests::get_data,
+    services::watchlist::set_queries,
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::cache::generate_cache_key,
+};
+use futures::{stream, StreamExt};
+use std::{collections::HashSet, error::Error};
+
+/// Search the registry for every title of the watchlist entry known as
+/// `title`, print what each finds and save the queries that together find
+/// the most books of the series as the entry's queries
+pub async fn discover(
+    ctx: &AppContext,
+    opts: &GlobalOpts,
+    title: &str,
+    sample: usize,
+) -> Result<(), Box<dyn Error>> {
+    let watchlist = super::load_enriched_watchlist(ctx, opts).await?;
+    let entry = watchlist
+        .iter()
+        .find(|entry| {
+            candidates(entry).iter().any(|c| same_title(c, title)) || entry.slug() == title
+        })
+        .ok_or_else(|| format!("{} is not in the watchlist, add it first", title))?;
+
+    let mut results = Vec::new();
+    let mut searches = stream::iter(candidates(entry))
+        .map(|query| async move {
+            let rows = get_data::extract_table_data(ctx, &query).await;
+            (query, rows.map_err(|e| e.to_string()))
+        })
+        .buffered(ctx.config.fetch.concurrency);
+
+    while let Some((query, rows)) = searches.next().await {
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => {
+                eprintln!("Failed to search {}: {}", query, e);
+                continue;
+            }
+        };
+        let matching = rows.iter().filter(|row| is_match(&query, row)).count();
+        println!("{} rows, {} matching: {}", rows.len(), matching, query);
+        for row in rows.iter().take(sample) {
+            println!("  {} | {}", row.isbn, row.title);
+        }
+        results.push((query, rows));
+    }
+
+    let queries = pick_queries(&results);
+    if queries.is_empty() {
+        println!(
+            "No title matched any book, {} is left unchanged",
+            entry.name
+        );
+    } else if entry.queries() == queries {
+        println!("Queries unchanged: {}", queries.join(", "));
+    } else {
+        set_queries(ctx, &entry.name, &queries, opts.dry_run)?;
+        println!("Queries: {}", queries.join(", "));
+    }
+    Ok(())
+}
+
+/// The name, the alternate titles and the synonyms of `entry`, each once
+fn candidates(entry: &WatchlistEntry) -> Vec<String> {
+    let mut other: Vec<&String> = entry.other.values().collect();
+    other.sort();
+
+    let mut candidates: Vec<String> = Vec::new();
+    let titles = [&entry.name]
+        .into_iter()
+        .chain(entry.queries.iter())
+        .chain(other)
+        .chain(entry.synonyms.iter());
+    for title in titles {
+        let title = title.trim();
+        if !title.is_empty() && !candidates.iter().any(|c| same_title(c, title)) {
+            candidates.push(title.to_string());
+        }
+    }
+    candidates
+}
+
+fn normalize(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn same_title(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+/// The registry also matches authors, publishers and parts of words, only
+/// rows with the query in their title count
+fn is_match(query: &str, row: &BookRow) -> bool {
+    normalize(&row.title).contains(&normalize(query))
+}
+
+/// Greedily take the query matching the most books not matched by an
+/// earlier pick, until no query adds any. Ties go to the earlier candidate
+fn pick_queries(results: &[(String, Vec<BookRow>)]) -> Vec<String> {
+    let mut matched: Vec<(&String, HashSet<String>)> = results
+        .iter()
+        .map(|(query, rows)| {
+            let keys = rows
+                .iter()
+                .filter(|row| is_match(query, row))
+                .map(generate_cache_key)
+                .collect();
+            (query, keys)
+        })
+        .collect();
+
+    let mut covered = HashSet::new();
+    let mut picked = Vec::new();
+    loop {
+        let best = matched
+            .iter()
+            .enumerate()
+            .map(|(i, (_, keys))| (i, keys.difference(&covered).count()))
+            .filter(|(_, new)| *new > 0)
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+        let Some((i, _)) = best else {
+            break;
+        };
+
+        let (query, keys) = matched.remove(i);
+        covered.extend(keys);
+        picked.push(query.clone());
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_pick_queries() {
+        let entry = WatchlistEntry {
+            name: "Dược sư tự sự".to_string(),
+            other: HashMap::from([
+                ("romaji".to_string(), "Kusuriya no Hitorigoto".to_string()),
+                ("english".to_string(), "dược  SƯ tự sự".to_string()),
+            ]),
+            synonyms: vec!["Nhật ký dược sư".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            candidates(&entry),
+            ["Dược sư tự sự", "Kusuriya no Hitorigoto", "Nhật ký dược sư"]
+        );
+
+        let results = vec![
+            (
+                "Dược sư tự sự".to_string(),
+                vec![
+                    BookRow::sample("1", "Dược Sư Tự Sự - Tập 1"),
+                    BookRow::sample("2", "Dược Sư Tự Sự - Tập 2"),
+                    // Found through the author or publisher
+                    BookRow::sample("9", "Sách khác"),
+                ],
+            ),
+            ("Kusuriya no Hitorigoto".to_string(), vec![]),
+            (
+                "Nhật ký dược sư".to_string(),
+                vec![
+                    BookRow::sample("2", "Dược Sư Tự Sự - Tập 2"),
+                    BookRow::sample("3", "Nhật ký dược sư - Tập 3"),
+                ],
+            ),
+        ];
+        assert_eq!(pick_queries(&results), ["Dược sư tự sự", "Nhật ký dược sư"]);
+        assert!(pick_queries(&results[1..2]).is_empty());
+    }
+}
@@ -0,0 +1,34 @@
+//! Subcommands of the `vbt` binary
+pub mod cache;
+pub mod daemon;
+pub mod discover;
+pub mod notify_test;
+pub mod rebuild;
+pub mod run;
+pub mod search;
+pub mod serve;
+pub mod watchlist;
+
+use crate::{
+    cli::GlobalOpts,
+    context::AppContext,
+    services::{anilist, watchlist::load_watchlist},
+    types::watchlist::WatchlistEntry,
+};
+
+/// The watchlist, enriched from AniList when `anilist.enabled` is set
+pub(crate) async fn load_enriched_watchlist(
+    ctx: &AppContext,
+    opts: &GlobalOpts,
+) -> Result<Vec<WatchlistEntry>, String> {
+    let mut watchlist = load_watchlist(ctx)?;
+    if ctx.config.anilist.enabled {
+        let enriched = anilist::enrich(ctx, &mut watchlist, opts.dry_run).await?;
+        if opts.verbose {
+            for entry in enriched {
+                println!("Enriched: {} ({})", entry.name, entry.fields.join(", "));
+            }
+        }
+    }
+    Ok(watchlist)
+}
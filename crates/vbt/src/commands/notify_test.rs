@@ -0,0 +1,83 @@
+use crate::{
+    cli::GlobalOpts,
+    context::AppContext,
+    notifier::{Event, RunStats},
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::time::generate_unix_timestamp,
+};
+use std::{error::Error, time::Instant};
+
+/// Send a made up new book through the context's notifiers. The caller
+/// builds them with `notify.enabled` forced on
+pub async fn notify_test(ctx: &AppContext, opts: &GlobalOpts) -> Result<(), Box<dyn Error>> {
+    if opts.dry_run {
+        println!(
+            "Would send a test notification to {:?} (dry run)",
+            ctx.notify.backends
+        );
+        return Ok(());
+    }
+    if !ctx.has_notifiers().await {
+        println!("No notifier is enabled in {}", ctx.config.notify.file);
+        return Ok(());
+    }
+
+    let entry = WatchlistEntry {
+        name: "[Test] VBT".to_string(),
+        ..Default::default()
+    };
+    let book = BookRow {
+        stt: "1".to_string(),
+        isbn: "978-604-0-00000-0".to_string(),
+        title: "[Test] VBT - Tập 1".to_string(),
+        author: "VBT".to_string(),
+        translator: "VBT".to_string(),
+        quantity: "1".to_string(),
+        ..Default::default()
+    };
+
+    // The whole run is played so every backend sends what it would send,
+    // e.g. the status message and the email digest
+    let started = Instant::now();
+    let id = generate_unix_timestamp();
+    let mut errors = ctx
+        .emit(Event::RunStarted {
+            id,
+            entries: 1,
+            test: true,
+        })
+        .await;
+    errors.extend(
+        ctx.emit(Event::EntryProcessed {
+            entry: &entry,
+            rows: 1,
+            new_books: &[&book],
+            covers: &[entry.cover.as_str()],
+        })
+        .await,
+    );
+    let stats = RunStats {
+        entries: 1,
+        processed: 1,
+        new_books: 1,
+        duration: started.elapsed(),
+        ..Default::default()
+    };
+    errors.extend(
+        ctx.emit(Event::RunFinished {
+            stats: &stats,
+            error: None,
+            next_run: id + 24 * 3600,
+        })
+        .await,
+    );
+    if let Err(e) = ctx.finish().await {
+        errors.extend(e.lines().map(str::to_string));
+    }
+
+    if !errors.is_empty() {
+        return Err(format!("Some backends failed the test:\n{}", errors.join("\n")).into());
+    }
+    println!("Sent a test notification, check each backend");
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::{
+    cli::GlobalOpts,
+    context::AppContext,
+    file_ops::{json_ops::read_json, opml_ops, rss_ops},
+    types::book::BookRow,
+};
+use serde::Deserialize;
+use std::{error::Error, path::Path};
+
+/// The documents written to `feed/json` by `run`
+#[derive(Deserialize)]
+pub struct FeedJson {
+    pub query: String,
+    pub rows: Vec<BookRow>,
+}
+
+/// Rows last fetched for the feed `slug`, `None` if it was never fetched
+pub fn load_feed_json(ctx: &AppContext, slug: &str) -> Result<Option<FeedJson>, String> {
+    let path = ctx.path(&ctx.config.paths.json_file(slug));
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+    let data = read_json(&path)?;
+    serde_json::from_str(&data)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+/// Regenerate every RSS feed and the OPML from the rows in `paths.json_dir`,
+/// without querying the registry or notifying anyone
+pub async fn rebuild_feeds(ctx: &AppContext, opts: &GlobalOpts) -> Result<(), Box<dyn Error>> {
+    let watchlist = super::load_enriched_watchlist(ctx, opts).await?;
+
+    for entry in &watchlist {
+        let slug = entry.slug();
+        let Some(feed_json) = load_feed_json(ctx, &slug)? else {
+            if opts.verbose {
+                println!("Skipped: {} (never fetched)", entry.name);
+            }
+            continue;
+        };
+
+        let update = rss_ops::generate_and_save_rss(ctx, &feed_json.rows, entry, opts.dry_run)?;
+        if update.has_changes() {
+            println!(
+                "Rebuilt: {} ({} added, {} changed, {} removed)",
+                entry.name,
+                update.added.len(),
+                update.changed.len(),
+                update.removed.len()
+            );
+        } else if update.written {
+            println!("Rebuilt: {} (no new books, feed refreshed)", entry.name);
+        } else if opts.verbose {
+            println!("Rebuilt: {} (unchanged)", entry.name);
+        }
+    }
+
+    if !opts.dry_run {
+        opml_ops::generate_and_save_opml(ctx, &watchlist)?;
+        println!("Generated: {}", ctx.config.paths.opml);
+    }
+
+    Ok(())
+}
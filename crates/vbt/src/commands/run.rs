@@ -0,0 +1,313 @@
+use crate::{
+    cli::GlobalOpts,
+    context::AppContext,
+    file_ops::{json_ops, opml_ops, rss_ops},
+    json::return_json::return_json,
+    notifier::{Event, RunStats},
+    requests::get_data::{self, FetchStats},
+    services::{
+        cover_resolver::CoverResolver,
+        covers::{self, Covers},
+        run_report::{self, EntryReport, EntryStatus, RunReport},
+    },
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::{cache::generate_cache_key, time::generate_unix_timestamp},
+};
+use chrono::{Duration, Utc};
+use futures::{stream, StreamExt};
+use std::{collections::HashSet, error::Error, time::Instant};
+use tokio::sync::watch;
+
+/// The watchlist entries a run goes through
+#[derive(Debug, Clone, Copy)]
+pub enum Selection<'a> {
+    All,
+    /// By name or feed name, an error when no entry matches
+    Named(&'a str),
+    /// By feed name
+    Slugs(&'a HashSet<String>),
+}
+
+impl Selection<'_> {
+    fn contains(&self, entry: &WatchlistEntry) -> bool {
+        match self {
+            Selection::All => true,
+            Selection::Named(name) => entry.name == *name || entry.slug() == *name,
+            Selection::Slugs(slugs) => slugs.contains(&entry.slug()),
+        }
+    }
+}
+
+/// Fetch the watchlist, or only the entry named `only`, update the feeds
+/// and notify the context's backends. A dry run writes nothing
+pub async fn run(
+    ctx: &AppContext,
+    opts: &GlobalOpts,
+    only: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let selection = only.map_or(Selection::All, Selection::Named);
+    // Scheduled runs happen once a day
+    let next_run = (Utc::now() + Duration::hours(24)).timestamp();
+    let (_, stop) = watch::channel(false);
+    run_selected(ctx, opts, selection, next_run, &stop).await?;
+    Ok(())
+}
+
+/// A run that stopped on an error, with the report of the entries it
+/// got through
+#[derive(Debug, thiserror::Error)]
+#[error("{error}")]
+pub struct RunFailed {
+    pub report: RunReport,
+    pub error: Box<dyn Error>,
+}
+
+/// [`run`] for the entries in `selection`, announcing `next_run` as the
+/// next one. Once `stop` turns true the entry being written is finished
+/// and the rest are left for later. The report of the run is saved in
+/// `paths.runs`, unless it's a dry run, and returned, along with the
+/// error if the run stopped on one
+pub async fn run_selected(
+    ctx: &AppContext,
+    opts: &GlobalOpts,
+    selection: Selection<'_>,
+    next_run: i64,
+    stop: &watch::Receiver<bool>,
+) -> Result<RunReport, RunFailed> {
+    let started = Instant::now();
+    let mut report = RunReport::new(generate_unix_timestamp());
+    let mut stats = RunStats::default();
+
+    let result = process_watchlist(ctx, opts, selection, stop, &mut report, &mut stats).await;
+
+    stats.duration = started.elapsed();
+    let error = result.as_ref().err().map(|e| e.to_string());
+    report.finish(stats.entries, error.clone());
+    if !opts.dry_run {
+        // A missing report shouldn't fail a run that went fine
+        match report.save(&ctx.path(&ctx.config.paths.runs)) {
+            Ok(path) if opts.verbose => println!("Report: {}", path),
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to save the run report: {}", e),
+        }
+    }
+    ctx.emit(Event::RunFinished {
+        stats: &stats,
+        error: error.as_deref(),
+        next_run,
+    })
+    .await;
+    let result = match (result, ctx.finish().await) {
+        (Err(e), Err(finish)) => {
+            eprintln!("Failed to finish the notifiers: {}", finish);
+            Err(e)
+        }
+        (result, finished) => result.and(finished.map_err(Into::into)),
+    };
+
+    match result {
+        Ok(()) => Ok(report),
+        Err(error) => Err(RunFailed { report, error }),
+    }
+}
+
+async fn process_watchlist(
+    ctx: &AppContext,
+    opts: &GlobalOpts,
+    selection: Selection<'_>,
+    stop: &watch::Receiver<bool>,
+    report: &mut RunReport,
+    stats: &mut RunStats,
+) -> Result<(), Box<dyn Error>> {
+    let config = &ctx.config;
+    let watchlist = super::load_enriched_watchlist(ctx, opts).await?;
+    let series_covers: Vec<&str> = watchlist.iter().map(|e| e.cover.as_str()).collect();
+    let downloaded = covers::cache_covers(ctx, &series_covers, opts.dry_run).await?;
+    if opts.verbose && downloaded > 0 {
+        println!("Downloaded {} cover(s)", downloaded);
+    }
+    let mut resolver = CoverResolver::load(ctx)?;
+    let selected: Vec<_> = watchlist
+        .iter()
+        .filter(|entry| selection.contains(entry))
+        .collect();
+    if let Selection::Named(name) = selection {
+        if selected.is_empty() {
+            return Err(format!("{} is not in the watchlist", name).into());
+        }
+    }
+
+    stats.entries = selected.len();
+    ctx.emit(Event::RunStarted {
+        id: report.id,
+        entries: selected.len(),
+        test: false,
+    })
+    .await;
+
+    // Up to `fetch.concurrency` entries are fetched at once, the results
+    // are still handled one by one in watchlist order
+    let mut fetches = stream::iter(selected)
+        .map(|entry| async move {
+            let started = Instant::now();
+            let mut fetch = FetchStats::default();
+            let rows = get_data::extract_entry_data(ctx, entry, &mut fetch).await;
+            let rows = rows.map_err(|e| (run_report::error_kind(e.as_ref()), e.to_string()));
+            let entry_report =
+                EntryReport::new(&entry.name, entry.slug(), fetch, started.elapsed());
+            (entry, rows, entry_report)
+        })
+        .buffered(config.fetch.concurrency);
+
+    while let Some((entry, rows, mut entry_report)) = fetches.next().await {
+        if *stop.borrow() {
+            println!(
+                "Stopping, {} entries left for later",
+                stats.entries - stats.processed
+            );
+            break;
+        }
+        let processing = Instant::now();
+        let rows = match rows {
+            Ok(data) => data,
+            Err((kind, e)) => {
+                eprintln!("Failed to fetch data for {}: {}", entry.name, e);
+                entry_report.status = EntryStatus::Failed;
+                entry_report.error_kind = Some(kind);
+                entry_report.error = Some(e.clone());
+                report.entries.push(entry_report);
+                stats.processed += 1;
+                stats.failed += 1;
+                ctx.emit(Event::EntryFailed { entry, error: &e }).await;
+                continue;
+            }
+        };
+        if opts.verbose {
+            println!("Fetched: {} ({} rows)", entry.name, rows.len());
+        }
+        entry_report.rows = rows.len();
+        if rows.is_empty() {
+            eprintln!("Entry {} is empty, skipping", entry.name);
+            stats.processed += 1;
+            stats.empty += 1;
+            entry_report.status = EntryStatus::Empty;
+            report.entries.push(entry_report);
+            ctx.emit(Event::EntryProcessed {
+                entry,
+                rows: 0,
+                new_books: &[],
+                covers: &[],
+            })
+            .await;
+        } else {
+            // Volume covers are resolved before the feed is built from them
+            let found = resolver.lookup(ctx, &rows).await;
+            if !opts.dry_run {
+                resolver.save()?;
+            }
+            let book_covers: Vec<&str> = rows
+                .iter()
+                .map(|book| resolver.resolve(&book.isbn, &entry.cover))
+                .collect();
+            let downloaded = covers::cache_covers(ctx, &book_covers, opts.dry_run).await?;
+            if opts.verbose && found + downloaded > 0 {
+                println!(
+                    "Covers: {} ({} found, {} downloaded)",
+                    entry.name, found, downloaded
+                );
+            }
+
+            // Generate JSON for json file
+            let json_data = return_json(&entry.name, &rows).map_err(|e| {
+                Box::<dyn Error>::from(format!("Failed to generate JSON for {}: {}", entry.name, e))
+            })?;
+
+            let filename_base = entry.slug();
+            let json_path = ctx.path(&config.paths.json_file(&filename_base));
+
+            let json_written = if opts.dry_run {
+                json_ops::json_differs(&json_data, &json_path)
+            } else {
+                json_ops::save_json(&json_data, &json_path)?
+            };
+
+            let update = rss_ops::generate_and_save_rss(ctx, &rows, entry, opts.dry_run)?;
+            if update.has_changes() {
+                println!(
+                    "Processed: {} ({} added, {} changed, {} removed)",
+                    entry.name,
+                    update.added.len(),
+                    update.changed.len(),
+                    update.removed.len()
+                );
+            } else if update.written || json_written {
+                println!("Processed: {} (no new books, files refreshed)", entry.name);
+            } else {
+                println!("Processed: {} (unchanged)", entry.name);
+            }
+            if opts.verbose {
+                for key in &update.added {
+                    println!("  added: {}", key);
+                }
+                for key in &update.changed {
+                    println!("  changed: {}", key);
+                }
+                for key in &update.removed {
+                    println!("  removed: {}", key);
+                }
+            }
+
+            // Notifiers link our copies of the covers, like the feed does
+            let cached = Covers::load(ctx)?;
+            let (new_books, new_covers): (Vec<&BookRow>, Vec<String>) = rows
+                .iter()
+                .zip(book_covers.iter().copied())
+                .filter(|(book, _)| {
+                    let key = generate_cache_key(book);
+                    update.added.contains(&key) || update.changed.contains(&key)
+                })
+                .map(|(book, cover)| (book, cached.link(cover).url))
+                .unzip();
+            let new_covers: Vec<&str> = new_covers.iter().map(String::as_str).collect();
+            let notified = WatchlistEntry {
+                cover: cached.link(&entry.cover).url,
+                ..WatchlistEntry::clone(entry)
+            };
+
+            entry_report.new = update.added.len();
+            entry_report.changed = update.changed.len();
+            entry_report.removed = update.removed.len();
+            entry_report.process_ms = run_report::millis(processing.elapsed());
+            report.entries.push(entry_report);
+
+            stats.processed += 1;
+            stats.new_books += new_books.len();
+            ctx.emit(Event::EntryProcessed {
+                entry: &notified,
+                rows: rows.len(),
+                new_books: &new_books,
+                covers: &new_covers,
+            })
+            .await;
+        }
+    }
+
+    // Not a single row for the whole watchlist means the registry page
+    // changed or blocks us, not that every series went quiet
+    if stats.entries > 0 && stats.failed + stats.empty == stats.entries {
+        ctx.emit(Event::ScraperBroken {
+            error: &format!(
+                "None of the {} entries returned any rows ({} failed, {} empty)",
+                stats.entries, stats.failed, stats.empty
+            ),
+        })
+        .await;
+    }
+
+    if !opts.dry_run {
+        opml_ops::generate_and_save_opml(ctx, &watchlist)?;
+        println!("Generated: {}", config.paths.opml);
+    }
+
+    Ok(())
+}
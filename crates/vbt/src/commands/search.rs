@@ -0,0 +1,84 @@
+use crate::{
+    context::AppContext, json::return_json::return_json, requests::get_data, types::book::BookRow,
+};
+use std::error::Error;
+
+/// Search the registry for `query` and print the rows as a table or JSON
+pub async fn search(ctx: &AppContext, query: &str, json: bool) -> Result<(), Box<dyn Error>> {
+    let rows = get_data::extract_table_data(ctx, query).await?;
+
+    if json {
+        println!("{}", return_json(query, &rows)?);
+    } else if rows.is_empty() {
+        println!("No rows for {}", query);
+    } else {
+        print!("{}", render_table(&rows));
+    }
+    Ok(())
+}
+
+fn render_table(rows: &[BookRow]) -> String {
+    let header = ["ISBN", "Title", "Translator", "Print run", "Partner"];
+    let cells: Vec<[&str; 5]> = rows
+        .iter()
+        .map(|row| {
+            [
+                row.isbn.as_str(),
+                row.title.as_str(),
+                row.translator.as_str(),
+                row.quantity.as_str(),
+                row.partner.as_str(),
+            ]
+        })
+        .collect();
+
+    // Titles are Vietnamese, so widths are counted in chars, not bytes
+    let mut widths = header.map(|cell| cell.chars().count());
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |row: &[&str; 5]| {
+        let padded: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| {
+                let padding = width - cell.chars().count();
+                format!("{}{}", cell, " ".repeat(padding))
+            })
+            .collect();
+        format!("{}\n", padded.join(" | ").trim_end())
+    };
+
+    let mut table = line(&header);
+    let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    table.push_str(&format!("{}\n", rule.join("-+-")));
+    for row in &cells {
+        table.push_str(&line(row));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_table() {
+        let row = BookRow {
+            translator: "Hà".to_string(),
+            quantity: "2000".to_string(),
+            partner: "IPM".to_string(),
+            ..BookRow::sample("978-604", "Dược Sư Tự Sự")
+        };
+
+        assert_eq!(
+            render_table(&[row]),
+            "ISBN    | Title         | Translator | Print run | Partner\n\
+             --------+---------------+------------+-----------+--------\n\
+             978-604 | Dược Sư Tự Sự | Hà         | 2000      | IPM\n"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{
+    cli::GlobalOpts,
+    commands::{
+        daemon::shutdown_signal,
+        run::{self, Selection},
+    },
+    context::AppContext,
+    server::{router, snapshot::Snapshot, SharedSnapshot},
+};
+use chrono::Utc;
+use std::{
+    error::Error,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{net::TcpListener, sync::watch, time::MissedTickBehavior};
+
+/// Serve the feeds and the JSON API on `serve.bind`. Every
+/// `serve.refresh_minutes`, starting right away, the watchlist is run
+/// as `vbt run` would and the served data is swapped for the new files.
+/// SIGTERM or Ctrl-C stop both once the entry being written is done
+pub async fn serve(ctx: &AppContext, opts: &GlobalOpts) -> Result<(), Box<dyn Error>> {
+    let config = &ctx.config.serve;
+    let snapshot: SharedSnapshot = Arc::new(RwLock::new(Arc::new(Snapshot::load(ctx).await?)));
+
+    let listener = TcpListener::bind(&config.bind)
+        .await
+        .map_err(|e| format!("Failed to listen on {}: {}", config.bind, e))?;
+    println!("Serving on {} as {}", config.bind, config.public_url);
+
+    let (stopping, stop) = watch::channel(false);
+    let stopping = Arc::new(stopping);
+    let signal = shutdown_signal()?;
+    tokio::spawn({
+        let stopping = stopping.clone();
+        async move {
+            signal.await;
+            println!("Stopping once the current refresh is written");
+            let _ = stopping.send(true);
+        }
+    });
+
+    let mut server_stop = stop.clone();
+    let server = async {
+        let served = axum::serve(listener, router(snapshot.clone()))
+            .with_graceful_shutdown(async move {
+                let _ = server_stop.wait_for(|stop| *stop).await;
+            })
+            .await;
+        // A server that fails stops the refresh too
+        let _ = stopping.send(true);
+        served
+    };
+
+    // Runs in this task, the run is not Send
+    let refresh = async {
+        if config.refresh_minutes == 0 {
+            return;
+        }
+        let every = Duration::from_secs(config.refresh_minutes * 60);
+        let mut stop = stop.clone();
+        let mut interval = tokio::time::interval(every);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = stop.wait_for(|stop| *stop) => return,
+            }
+            let next_run = (Utc::now() + every).timestamp();
+            if let Err(e) = run::run_selected(ctx, opts, Selection::All, next_run, &stop).await {
+                eprintln!("Refresh failed: {}", e);
+            }
+            if *stop.borrow() {
+                return;
+            }
+            match Snapshot::load(ctx).await {
+                Ok(loaded) => {
+                    *snapshot.write().unwrap_or_else(|p| p.into_inner()) = Arc::new(loaded);
+                }
+                Err(e) => eprintln!("Failed to reload the served files: {}", e),
+            }
+        }
+    };
+
+    // A stop lets the refresh finish the entry it is writing
+    let (served, ()) = tokio::join!(server, refresh);
+    served?;
+    println!("Stopped");
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use crate::{
+    cli::{AddArgs, GlobalOpts},
+    context::AppContext,
+    services::watchlist::{add_to_watchlist, remove_from_watchlist},
+    types::watchlist::WatchlistEntry,
+};
+use std::{collections::HashMap, error::Error, fs, io::ErrorKind};
+
+pub fn add(ctx: &AppContext, opts: &GlobalOpts, args: AddArgs) -> Result<(), Box<dyn Error>> {
+    let mut other = HashMap::new();
+    for (key, title) in [
+        ("native", args.native),
+        ("romaji", args.romaji),
+        ("english", args.english),
+    ] {
+        if let Some(title) = title {
+            other.insert(key.to_string(), title);
+        }
+    }
+
+    let entry = WatchlistEntry {
+        name: args.name,
+        cover: args.cover,
+        other,
+        tags: args.tags,
+        anilist: args.anilist,
+        interval_hours: args.interval_hours,
+        ..Default::default()
+    };
+    add_to_watchlist(ctx, &entry, opts.dry_run)?;
+    println!(
+        "Added: {} ({})",
+        entry.name,
+        ctx.config.paths.rss_file(&entry.slug())
+    );
+    Ok(())
+}
+
+/// The entry's feed and JSON are deleted too, so the next run or
+/// `rebuild-feeds` drops it from the OPML. `cache prune` forgets its dates
+pub fn remove(ctx: &AppContext, opts: &GlobalOpts, name: &str) -> Result<(), Box<dyn Error>> {
+    let entry = remove_from_watchlist(ctx, name, opts.dry_run)?;
+    println!("Removed: {}", entry.name);
+    if opts.dry_run {
+        return Ok(());
+    }
+
+    let paths = &ctx.config.paths;
+    let slug = entry.slug();
+    for path in [paths.rss_file(&slug), paths.json_file(&slug)] {
+        match fs::remove_file(ctx.path(&path)) {
+            Ok(()) => println!("Deleted: {}", path),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to delete {}: {}", path, e).into()),
+        }
+    }
+    Ok(())
+}
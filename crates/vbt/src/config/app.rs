@@ -0,0 +1,580 @@
+use crate::utils::schedule::Schedule;
+use chrono_tz::Tz;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{env, fs, io::ErrorKind, net::SocketAddr};
+use toml::{Table, Value};
+
+pub const DEFAULT_CONFIG_PATH: &str = "vbt.toml";
+
+/// Settings loaded from `vbt.toml`, then overridden by the environment
+/// and then by `--set key=value` on the command line.
+///
+/// `VBT_<SECTION>_<KEY>` sets `<section>.<key>`, e.g. `VBT_PATHS_RSS_DIR`.
+/// `FT_WEBHOOK` and `FEED_BASE_URL` are still read for older setups
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub paths: Paths,
+    pub urls: Urls,
+    pub time: TimeConfig,
+    pub fetch: FetchConfig,
+    pub notify: NotifySettings,
+    pub anilist: AniListConfig,
+    pub covers: CoversConfig,
+    pub serve: ServeConfig,
+    pub daemon: DaemonConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Paths {
+    pub watchlist: String,
+    pub date_cache: String,
+    /// Rows fetched for each entry, `<slug>.json`
+    pub json_dir: String,
+    /// Published feeds, `<slug>.rss`
+    pub rss_dir: String,
+    pub opml: String,
+    /// Downloaded covers and their thumbnails
+    pub covers: String,
+    /// A report of every run, `<id>.json`
+    pub runs: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Urls {
+    /// Where `rss_dir` is publicly reachable
+    pub feed_base: String,
+    /// Registry search page, queried with `?query=`
+    pub registry: String,
+    /// Link of every channel and item
+    pub link: String,
+    /// Where `paths.covers` is publicly reachable
+    pub covers_base: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeConfig {
+    /// IANA name of the zone used for displayed times
+    pub timezone: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    /// Entries fetched from the registry at the same time
+    pub concurrency: usize,
+    /// Attempts when the registry page has no table
+    pub retries: u32,
+    pub retry_delay_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifySettings {
+    /// Nothing is sent unless this is set
+    pub enabled: bool,
+    /// Backends, targets and routes, see `NotifyConfig`
+    pub file: String,
+    /// Discord messages that could not be delivered
+    pub dead_letter: String,
+}
+
+/// Fills in titles, cover and genres of entries with an `anilist` id
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AniListConfig {
+    pub enabled: bool,
+    /// GraphQL endpoint
+    pub api_url: String,
+    /// Fetched media by id
+    pub cache: String,
+    /// Hours before a cached media is fetched again
+    pub ttl_hours: u64,
+}
+
+/// Serve covers from our own copy instead of hotlinking them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoversConfig {
+    pub enabled: bool,
+    /// Longest side of the thumbnails, in pixels
+    pub thumbnail_size: u32,
+    /// Cover url by ISBN, for volumes the lookup gets wrong
+    pub overrides: String,
+    /// Where volume covers are looked up by ISBN, `openlibrary` or `none`
+    pub lookup: String,
+    pub lookup_url: String,
+    /// Covers found by ISBN
+    pub lookup_cache: String,
+}
+
+/// `vbt serve`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServeConfig {
+    /// Address the server listens on
+    pub bind: String,
+    /// Where the server is reachable, used for the self links of feeds
+    pub public_url: String,
+    /// Minutes between refreshes from the registry, 0 to only serve
+    pub refresh_minutes: u64,
+}
+
+/// `vbt daemon`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// When to wake up and check the entries that are due, a cron
+    /// expression or an interval such as `1h`, in `time.timezone`
+    pub schedule: String,
+    /// Hours between checks of an entry without its own `interval_hours`
+    pub entry_interval_hours: u64,
+    /// Up to this many minutes of random delay before the first check
+    pub jitter_minutes: u64,
+    /// When each entry was last checked
+    pub state: String,
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Paths {
+            watchlist: "watchlist.json".to_string(),
+            date_cache: "date_cache.json".to_string(),
+            json_dir: "feed/json".to_string(),
+            rss_dir: "feed/rss".to_string(),
+            opml: "feed/feeds.opml".to_string(),
+            covers: "feed/covers".to_string(),
+            runs: "runs".to_string(),
+        }
+    }
+}
+
+impl Default for Urls {
+    fn default() -> Self {
+        Urls {
+            feed_base: "https://raw.githubusercontent.com/Irilith/VBT/refs/heads/main/feed/rss"
+                .to_string(),
+            registry: "https://ppdvn.gov.vn/web/guest/ke-hoach-xuat-ban".to_string(),
+            link: "https://github.com/Irilith/VBT".to_string(),
+            covers_base:
+                "https://raw.githubusercontent.com/Irilith/VBT/refs/heads/main/feed/covers"
+                    .to_string(),
+        }
+    }
+}
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        TimeConfig {
+            timezone: "Asia/Ho_Chi_Minh".to_string(),
+        }
+    }
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            concurrency: 1,
+            retries: 3,
+            retry_delay_ms: 5000,
+        }
+    }
+}
+
+impl Default for NotifySettings {
+    fn default() -> Self {
+        NotifySettings {
+            enabled: false,
+            file: "notify.json".to_string(),
+            dead_letter: "dead_letter.json".to_string(),
+        }
+    }
+}
+
+impl Default for AniListConfig {
+    fn default() -> Self {
+        AniListConfig {
+            enabled: false,
+            api_url: "https://graphql.anilist.co".to_string(),
+            cache: "anilist_cache.json".to_string(),
+            ttl_hours: 24 * 7,
+        }
+    }
+}
+
+impl Default for CoversConfig {
+    fn default() -> Self {
+        CoversConfig {
+            enabled: true,
+            thumbnail_size: 320,
+            overrides: "cover_overrides.json".to_string(),
+            lookup: "openlibrary".to_string(),
+            lookup_url: "https://covers.openlibrary.org/b/isbn".to_string(),
+            lookup_cache: "cover_lookup.json".to_string(),
+        }
+    }
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            bind: "127.0.0.1:8080".to_string(),
+            public_url: "http://localhost:8080".to_string(),
+            refresh_minutes: 6 * 60,
+        }
+    }
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            schedule: "1h".to_string(),
+            entry_interval_hours: 24,
+            jitter_minutes: 10,
+            state: "daemon_state.json".to_string(),
+        }
+    }
+}
+
+impl Paths {
+    pub fn json_file(&self, slug: &str) -> String {
+        format!("{}/{}.json", self.json_dir, slug)
+    }
+
+    pub fn rss_file(&self, slug: &str) -> String {
+        format!("{}/{}.rss", self.rss_dir, slug)
+    }
+}
+
+impl TimeConfig {
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::Asia__Ho_Chi_Minh)
+    }
+}
+
+/// Settings read from variables that predate `vbt.toml`
+const LEGACY_ENV: [(&str, &str); 2] = [
+    ("FT_WEBHOOK", "notify.enabled"),
+    ("FEED_BASE_URL", "urls.feed_base"),
+];
+
+/// A legacy flag as the old code took it: any common spelling of true
+/// or false, anything else is false with a warning instead of an error
+fn legacy_flag(name: &str, value: &str) -> bool {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => true,
+        "false" | "0" | "no" | "off" => false,
+        _ => {
+            eprintln!("{}: {} is not true or false, taken as false", name, value);
+            false
+        }
+    }
+}
+
+/// `<section>.<key>` set by the variable `name`, if any
+fn env_key(name: &str) -> Option<String> {
+    if let Some((_, key)) = LEGACY_ENV.iter().find(|(var, _)| *var == name) {
+        return Some(key.to_string());
+    }
+    let (section, key) = name.strip_prefix("VBT_")?.split_once('_')?;
+    Some(format!("{}.{}", section.to_lowercase(), key.to_lowercase()))
+}
+
+/// Set `key` in `table`, typed like the same key in `defaults`
+fn set(table: &mut Table, defaults: &Table, key: &str, value: &str) -> Result<(), String> {
+    let (section, field) = key
+        .split_once('.')
+        .ok_or_else(|| format!("{} is not a <section>.<key> name", key))?;
+    let default = defaults
+        .get(section)
+        .and_then(|s| s.get(field))
+        .ok_or_else(|| format!("unknown setting {}", key))?;
+
+    let value = match default {
+        Value::String(_) => Value::String(value.to_string()),
+        // Parsed as a TOML literal, so `4` and `true` keep their type
+        _ => format!("v = {}", value)
+            .parse::<Table>()
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .filter(|v| v.same_type(default))
+            .ok_or_else(|| format!("{} expects {}, got {}", key, default.type_str(), value))?,
+    };
+
+    let section = table
+        .entry(section)
+        .or_insert_with(|| Value::Table(Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| format!("{} is not a table", section))?;
+    section.insert(field.to_string(), value);
+    Ok(())
+}
+
+fn check_url(errors: &mut Vec<String>, key: &str, url: &mut String) {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {
+            *url = url.trim_end_matches('/').to_string();
+        }
+        Ok(_) => errors.push(format!("{} must be an http(s) url, got {}", key, url)),
+        Err(e) => errors.push(format!("{} is not a valid url ({}): {}", key, e, url)),
+    }
+}
+
+impl AppConfig {
+    /// Load `path`, or `vbt.toml` if it exists, then apply the environment
+    /// and `overrides` (`key=value`) and validate the result
+    pub fn load(path: Option<&str>, overrides: &[String]) -> Result<Self, String> {
+        Self::load_from(path, env::vars(), overrides)
+    }
+
+    fn load_from(
+        path: Option<&str>,
+        vars: impl IntoIterator<Item = (String, String)>,
+        overrides: &[String],
+    ) -> Result<Self, String> {
+        let source = path.unwrap_or(DEFAULT_CONFIG_PATH);
+        let mut table = match fs::read_to_string(source) {
+            Ok(data) => data
+                .parse::<Table>()
+                .map_err(|e| format!("Failed to parse {}: {}", source, e))?,
+            // Only the default file is optional
+            Err(e) if e.kind() == ErrorKind::NotFound && path.is_none() => Table::new(),
+            Err(e) => return Err(format!("Failed to open {}: {}", source, e)),
+        };
+
+        let defaults = Table::try_from(AppConfig::default()).expect("the defaults serialize");
+        let mut vars: Vec<_> = vars.into_iter().collect();
+        // Legacy names first, so the VBT_ names win
+        vars.sort_by_key(|(name, _)| name.starts_with("VBT_"));
+        for (name, value) in vars {
+            // Unset GitHub secrets show up as empty variables
+            if value.is_empty() {
+                continue;
+            }
+            // Other tools may use VBT_ too, only known sections are ours
+            let key = env_key(&name).filter(|key| {
+                key.split_once('.')
+                    .is_some_and(|(section, _)| defaults.contains_key(section))
+            });
+            if let Some(key) = key {
+                let is_flag = key
+                    .split_once('.')
+                    .and_then(|(section, field)| defaults.get(section)?.get(field))
+                    .is_some_and(Value::is_bool);
+                let is_legacy = LEGACY_ENV.iter().any(|(var, _)| *var == name);
+                let value = if is_flag && is_legacy {
+                    legacy_flag(&name, &value).to_string()
+                } else {
+                    value
+                };
+                set(&mut table, &defaults, &key, &value).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+        for assignment in overrides {
+            let (key, value) = assignment
+                .split_once('=')
+                .ok_or_else(|| format!("--set {}: expected KEY=VALUE", assignment))?;
+            set(&mut table, &defaults, key.trim(), value.trim())
+                .map_err(|e| format!("--set {}: {}", assignment, e))?;
+        }
+
+        let config: AppConfig = table
+            .try_into()
+            .map_err(|e| format!("Invalid {}: {}", source, e))?;
+        config
+            .validate()
+            .map_err(|errors| format!("Invalid configuration:\n  {}", errors.join("\n  ")))
+    }
+
+    /// Check every setting, returning all problems at once.
+    /// Urls lose their trailing slash
+    fn validate(mut self) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+
+        let paths = [
+            ("paths.watchlist", &self.paths.watchlist),
+            ("paths.date_cache", &self.paths.date_cache),
+            ("paths.json_dir", &self.paths.json_dir),
+            ("paths.rss_dir", &self.paths.rss_dir),
+            ("paths.opml", &self.paths.opml),
+            ("paths.covers", &self.paths.covers),
+            ("paths.runs", &self.paths.runs),
+            ("notify.file", &self.notify.file),
+            ("notify.dead_letter", &self.notify.dead_letter),
+            ("anilist.cache", &self.anilist.cache),
+            ("covers.overrides", &self.covers.overrides),
+            ("covers.lookup_cache", &self.covers.lookup_cache),
+            ("daemon.state", &self.daemon.state),
+        ];
+        for (key, path) in paths {
+            if path.trim().is_empty() {
+                errors.push(format!("{} must not be empty", key));
+            }
+        }
+
+        check_url(&mut errors, "urls.feed_base", &mut self.urls.feed_base);
+        check_url(&mut errors, "urls.registry", &mut self.urls.registry);
+        check_url(&mut errors, "urls.link", &mut self.urls.link);
+        check_url(&mut errors, "urls.covers_base", &mut self.urls.covers_base);
+        check_url(&mut errors, "anilist.api_url", &mut self.anilist.api_url);
+        check_url(
+            &mut errors,
+            "covers.lookup_url",
+            &mut self.covers.lookup_url,
+        );
+        check_url(&mut errors, "serve.public_url", &mut self.serve.public_url);
+
+        if self.time.timezone.parse::<Tz>().is_err() {
+            errors.push(format!(
+                "time.timezone must be an IANA name such as Asia/Ho_Chi_Minh, got {}",
+                self.time.timezone
+            ));
+        }
+        if self.fetch.concurrency == 0 {
+            errors.push("fetch.concurrency must be at least 1".to_string());
+        }
+        if self.fetch.retries == 0 {
+            errors.push("fetch.retries must be at least 1".to_string());
+        }
+        if self.covers.thumbnail_size == 0 {
+            errors.push("covers.thumbnail_size must be at least 1".to_string());
+        }
+        if self.serve.bind.parse::<SocketAddr>().is_err() {
+            errors.push(format!(
+                "serve.bind must be an address such as 127.0.0.1:8080, got {}",
+                self.serve.bind
+            ));
+        }
+        if let Err(e) = Schedule::parse(&self.daemon.schedule) {
+            errors.push(format!("daemon.schedule: {}", e));
+        }
+        if self.daemon.entry_interval_hours == 0 {
+            errors.push("daemon.entry_interval_hours must be at least 1".to_string());
+        }
+        if !["openlibrary", "none"].contains(&self.covers.lookup.as_str()) {
+            errors.push(format!(
+                "covers.lookup must be openlibrary or none, got {}",
+                self.covers.lookup
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::TempDir;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = TempDir::new("config");
+        fs::create_dir_all(dir.path()).unwrap();
+        let path = dir.path().join("vbt.toml");
+        fs::write(
+            &path,
+            "[paths]\nrss_dir = \"out/rss\"\nwatchlist = \"list.json\"\n\n[fetch]\nconcurrency = 2\n",
+        )
+        .unwrap();
+
+        let config = AppConfig::load_from(
+            path.to_str(),
+            vars(&[
+                ("VBT_PATHS_WATCHLIST", "env.json"),
+                ("VBT_FETCH_CONCURRENCY", "4"),
+                ("FT_WEBHOOK", "true"),
+                ("FEED_BASE_URL", "https://example.com/rss/"),
+                ("VBT_URLS_LINK", ""),
+                ("VBT_OTHER_TOOL", "x"),
+                ("HOME", "/root"),
+            ]),
+            &["fetch.concurrency=8".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(config.paths.rss_dir, "out/rss");
+        assert_eq!(config.paths.watchlist, "env.json");
+        assert_eq!(config.paths.rss_file("Slug"), "out/rss/Slug.rss");
+        assert_eq!(config.fetch.concurrency, 8);
+        assert!(config.notify.enabled);
+        assert_eq!(config.urls.feed_base, "https://example.com/rss");
+        assert_eq!(config.urls.link, "https://github.com/Irilith/VBT");
+        assert_eq!(config.time.tz(), Tz::Asia__Ho_Chi_Minh);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = AppConfig::load_from(None, vars(&[("VBT_FETCH_CONCURRENCY", "many")]), &[])
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "VBT_FETCH_CONCURRENCY: fetch.concurrency expects integer, got many"
+        );
+
+        // FT_WEBHOOK was read leniently, old secrets must keep working
+        let values = [
+            ("True", true),
+            ("1", true),
+            ("yes", true),
+            ("false", false),
+            ("0", false),
+            ("maybe", false),
+        ];
+        for (value, enabled) in values {
+            let config = AppConfig::load_from(None, vars(&[("FT_WEBHOOK", value)]), &[]).unwrap();
+            assert_eq!(config.notify.enabled, enabled, "FT_WEBHOOK={}", value);
+        }
+        // The new name stays strict
+        let err =
+            AppConfig::load_from(None, vars(&[("VBT_NOTIFY_ENABLED", "yes")]), &[]).unwrap_err();
+        assert_eq!(
+            err,
+            "VBT_NOTIFY_ENABLED: notify.enabled expects boolean, got yes"
+        );
+
+        let err = AppConfig::load_from(None, vec![], &["paths.nope=x".to_string()]).unwrap_err();
+        assert_eq!(err, "--set paths.nope=x: unknown setting paths.nope");
+
+        let err = AppConfig::load_from(
+            None,
+            vec![],
+            &[
+                "urls.registry=ftp://example.com".to_string(),
+                "time.timezone=Mars/Olympus".to_string(),
+                "fetch.concurrency=0".to_string(),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            "Invalid configuration:\n  \
+             urls.registry must be an http(s) url, got ftp://example.com\n  \
+             time.timezone must be an IANA name such as Asia/Ho_Chi_Minh, got Mars/Olympus\n  \
+             fetch.concurrency must be at least 1"
+        );
+
+        let err = AppConfig::load_from(None, vec![], &["daemon.schedule=weekly".to_string()])
+            .unwrap_err();
+        assert!(
+            err.contains("daemon.schedule: weekly is neither an interval nor a cron expression")
+        );
+
+        let err = AppConfig::load_from(Some("/nonexistent/vbt.toml"), vec![], &[]).unwrap_err();
+        assert!(err.starts_with("Failed to open /nonexistent/vbt.toml"));
+    }
+}
@@ -1 +1,2 @@
-pub mod flag;
+pub mod app;
+pub mod notify;
@@ -0,0 +1,493 @@
+use crate::types::watchlist::WatchlistEntry;
+use serde::Deserialize;
+use std::{env, fs::File, io::ErrorKind};
+
+/// Where notifications go, loaded from `notify.json`.
+///
+/// Webhook urls and tokens starting with `$` are read from that environment
+/// variable, so the file can be committed without leaking secrets
+#[derive(Deserialize, Debug, Clone)]
+pub struct NotifyConfig {
+    /// Which backends get notified
+    #[serde(default = "default_backends")]
+    pub backends: Vec<Backend>,
+    /// Run started / finished messages
+    #[serde(default = "default_daily")]
+    pub daily: Target,
+    /// Per entry messages when no route matches
+    #[serde(default = "default_processed")]
+    pub processed: Target,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Required when `backends` contains `telegram`
+    #[serde(default)]
+    pub telegram: Option<TelegramConfig>,
+    /// Generic JSON webhooks, used when `backends` contains `webhook`
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Required when `backends` contains `ntfy`
+    #[serde(default)]
+    pub ntfy: Option<NtfyConfig>,
+    /// Required when `backends` contains `email`
+    #[serde(default)]
+    pub email: Option<EmailConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Discord,
+    Telegram,
+    Webhook,
+    Ntfy,
+    Email,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TelegramConfig {
+    /// Bot token
+    #[serde(default = "default_telegram_token")]
+    pub token: String,
+    /// Chat ids or `@channel` usernames that get the new books
+    pub chats: Vec<String>,
+    /// Bot API server, defaults to api.telegram.org
+    #[serde(default)]
+    pub api_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Target {
+    pub webhook: String,
+    /// Role ids to mention
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+/// Sends the per entry messages of matching entries to another webhook
+#[derive(Deserialize, Debug, Clone)]
+pub struct Route {
+    /// Entry names this route applies to, `*` matches every entry
+    #[serde(default)]
+    pub entries: Vec<String>,
+    /// Watchlist tags this route applies to
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(flatten)]
+    pub target: Target,
+}
+
+/// Receives one signed JSON document per new book
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Key for the `X-VBT-Signature` HMAC-SHA256 header, unsigned without
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+/// Push notifications through an ntfy server, one per entry with new books
+#[derive(Deserialize, Debug, Clone)]
+pub struct NtfyConfig {
+    #[serde(default = "default_ntfy_server")]
+    pub server: String,
+    pub topic: String,
+    /// Access token for protected topics
+    #[serde(default)]
+    pub token: Option<String>,
+    /// 1 (min) to 5 (max), the server default when unset
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+/// A digest of the new books, mailed every `every_days`
+#[derive(Deserialize, Debug, Clone)]
+pub struct EmailConfig {
+    pub smtp: SmtpConfig,
+    /// e.g. `VBT <vbt@example.com>`
+    pub from: String,
+    #[serde(default = "default_email_subject")]
+    pub subject: String,
+    /// Days between digests, 0 sends one after every run with new books
+    #[serde(default = "default_every_days")]
+    pub every_days: u32,
+    /// Books waiting for the next digest
+    #[serde(default = "default_digest_path")]
+    pub state: String,
+    pub recipients: Vec<Subscription>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    #[default]
+    Starttls,
+    /// Plain text, only for local test servers
+    None,
+}
+
+/// A digest recipient. Without `entries` and `tags` they get every entry
+#[derive(Deserialize, Debug, Clone)]
+pub struct Subscription {
+    pub address: String,
+    /// Entry names, `*` matches every entry
+    #[serde(default)]
+    pub entries: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_email_subject() -> String {
+    "VBT: new books this week".to_string()
+}
+
+fn default_every_days() -> u32 {
+    7
+}
+
+fn default_digest_path() -> String {
+    "email_digest.json".to_string()
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_ntfy_server() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+fn default_backends() -> Vec<Backend> {
+    vec![Backend::Discord]
+}
+
+fn default_telegram_token() -> String {
+    "$TELEGRAM_TOKEN".to_string()
+}
+
+fn default_daily() -> Target {
+    Target {
+        webhook: "$DAILY".to_string(),
+        roles: Vec::new(),
+    }
+}
+
+fn default_processed() -> Target {
+    Target {
+        webhook: "$PROCESSED".to_string(),
+        roles: Vec::new(),
+    }
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        NotifyConfig {
+            backends: default_backends(),
+            daily: default_daily(),
+            processed: default_processed(),
+            routes: Vec::new(),
+            telegram: None,
+            webhooks: Vec::new(),
+            ntfy: None,
+            email: None,
+        }
+    }
+}
+
+impl NotifyConfig {
+    /// Load the config at `path`, a missing file gives the defaults
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(NotifyConfig::default()),
+            Err(e) => return Err(format!("Failed to open {}: {}", path, e)),
+        };
+
+        let config: NotifyConfig = serde_json::from_reader(file)
+            .map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+
+        let missing = [
+            (Backend::Telegram, "telegram", config.telegram.is_none()),
+            (Backend::Webhook, "webhooks", config.webhooks.is_empty()),
+            (Backend::Ntfy, "ntfy", config.ntfy.is_none()),
+            (Backend::Email, "email", config.email.is_none()),
+        ];
+        for (backend, section, is_missing) in missing {
+            if config.uses(backend) && is_missing {
+                return Err(format!(
+                    "{}: the {:?} backend is enabled but there is no \"{}\" section",
+                    path, backend, section
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn uses(&self, backend: Backend) -> bool {
+        self.backends.contains(&backend)
+    }
+
+    /// Every Discord webhook reference, as written in the file
+    pub fn discord_webhooks(&self) -> Vec<String> {
+        [&self.daily, &self.processed]
+            .into_iter()
+            .chain(self.routes.iter().map(|route| &route.target))
+            .map(|target| target.webhook.clone())
+            .collect()
+    }
+
+    /// Targets that should be notified about `entry`.
+    /// Falls back to `processed` when no route matches
+    pub fn targets_for(&self, entry: &WatchlistEntry) -> Vec<&Target> {
+        let targets: Vec<&Target> = self
+            .routes
+            .iter()
+            .filter(|route| route.matches(entry))
+            .map(|route| &route.target)
+            .collect();
+
+        if targets.is_empty() {
+            vec![&self.processed]
+        } else {
+            targets
+        }
+    }
+}
+
+impl Route {
+    pub fn matches(&self, entry: &WatchlistEntry) -> bool {
+        self.entries
+            .iter()
+            .any(|name| name == "*" || *name == entry.name)
+            || self.tags.iter().any(|tag| entry.tags.contains(tag))
+    }
+}
+
+/// Resolve a webhook reference, `$VAR` is read from the environment
+pub fn resolve_webhook(webhook: &str) -> Result<String, String> {
+    resolve_env("Webhook", webhook)
+}
+
+fn resolve_env(what: &str, value: &str) -> Result<String, String> {
+    resolve_with(what, value, |var| env::var(var))
+}
+
+/// [`resolve_env`] reading variables through `lookup`
+fn resolve_with(
+    what: &str,
+    value: &str,
+    lookup: impl Fn(&str) -> Result<String, env::VarError>,
+) -> Result<String, String> {
+    match value.strip_prefix('$') {
+        Some(var) => lookup(var).map_err(|e| format!("{} env {}: {}", what, var, e)),
+        None => Ok(value.to_string()),
+    }
+}
+
+impl TelegramConfig {
+    /// Bot token, resolving `$VAR` from the environment
+    pub fn token(&self) -> Result<String, String> {
+        resolve_env("Telegram token", &self.token)
+    }
+}
+
+impl WebhookConfig {
+    pub fn url(&self) -> Result<String, String> {
+        resolve_env("Webhook", &self.url)
+    }
+
+    pub fn secret(&self) -> Result<Option<String>, String> {
+        self.secret
+            .as_deref()
+            .map(|secret| resolve_env("Webhook secret", secret))
+            .transpose()
+    }
+}
+
+impl SmtpConfig {
+    /// Username and password, resolving `$VAR` from the environment
+    pub fn credentials(&self) -> Result<Option<(String, String)>, String> {
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => Ok(Some((
+                resolve_env("SMTP username", username)?,
+                resolve_env("SMTP password", password)?,
+            ))),
+            (None, None) => Ok(None),
+            _ => Err("SMTP username and password must be set together".to_string()),
+        }
+    }
+}
+
+impl Subscription {
+    pub fn matches(&self, name: &str, tags: &[String]) -> bool {
+        (self.entries.is_empty() && self.tags.is_empty())
+            || self
+                .entries
+                .iter()
+                .any(|entry| entry == "*" || entry == name)
+            || self.tags.iter().any(|tag| tags.contains(tag))
+    }
+}
+
+impl NtfyConfig {
+    pub fn token(&self) -> Result<Option<String>, String> {
+        self.token
+            .as_deref()
+            .map(|token| resolve_env("ntfy token", token))
+            .transpose()
+    }
+}
+
+impl Target {
+    /// Webhook url, resolving `$VAR` from the environment
+    pub fn url(&self) -> Result<String, String> {
+        resolve_webhook(&self.webhook)
+    }
+
+    /// Message content mentioning every role of this target
+    pub fn mention(&self) -> String {
+        self.roles
+            .iter()
+            .map(|role| format!("<@&{}>", role))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn entry(name: &str, tags: &[&str]) -> WatchlistEntry {
+        WatchlistEntry {
+            name: name.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn webhooks(targets: Vec<&Target>) -> Vec<&str> {
+        targets
+            .iter()
+            .map(|target| target.webhook.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_targets_for() {
+        let config: NotifyConfig = serde_json::from_str(
+            r#"{
+                "processed": { "webhook": "fallback" },
+                "routes": [
+                    { "entries": ["Alpha"], "webhook": "by-name" },
+                    { "tags": ["manga"], "webhook": "by-tag" },
+                    { "entries": ["Beta"], "tags": ["ln"], "webhook": "either" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            webhooks(config.targets_for(&entry("Alpha", &[]))),
+            ["by-name"]
+        );
+        assert_eq!(
+            webhooks(config.targets_for(&entry("Gamma", &["manga"]))),
+            ["by-tag"]
+        );
+        assert_eq!(
+            webhooks(config.targets_for(&entry("Alpha", &["manga", "ln"]))),
+            ["by-name", "by-tag", "either"]
+        );
+        assert_eq!(
+            webhooks(config.targets_for(&entry("Beta", &[]))),
+            ["either"]
+        );
+        // No route matches
+        assert_eq!(
+            webhooks(config.targets_for(&entry("Gamma", &["novel"]))),
+            ["fallback"]
+        );
+        assert_eq!(
+            webhooks(config.targets_for(&entry("alpha", &[]))),
+            ["fallback"]
+        );
+    }
+
+    #[test]
+    fn test_route_matches() {
+        let route = |entries: &[&str], tags: &[&str]| Route {
+            entries: entries.iter().map(|s| s.to_string()).collect(),
+            tags: tags.iter().map(|s| s.to_string()).collect(),
+            target: default_processed(),
+        };
+
+        assert!(route(&["*"], &[]).matches(&entry("Anything", &[])));
+        assert!(route(&[], &["manga"]).matches(&entry("Alpha", &["ln", "manga"])));
+        assert!(!route(&[], &["manga"]).matches(&entry("Alpha", &["ln"])));
+        assert!(route(&["Alpha"], &[]).matches(&entry("Alpha", &[])));
+        assert!(!route(&["Alpha"], &[]).matches(&entry("Beta", &["Alpha"])));
+        // A route without entries or tags matches nothing
+        assert!(!route(&[], &[]).matches(&entry("Alpha", &["manga"])));
+    }
+
+    #[test]
+    fn test_subscription_matches() {
+        let subscription = |entries: &[&str], tags: &[&str]| Subscription {
+            address: "reader@example.com".to_string(),
+            entries: entries.iter().map(|s| s.to_string()).collect(),
+            tags: tags.iter().map(|s| s.to_string()).collect(),
+        };
+        let manga = ["manga".to_string()];
+
+        // Unlike a route, a subscription without entries or tags gets everything
+        assert!(subscription(&[], &[]).matches("Alpha", &[]));
+        assert!(subscription(&["*"], &[]).matches("Alpha", &[]));
+        assert!(subscription(&[], &["manga"]).matches("Alpha", &manga));
+        assert!(!subscription(&[], &["manga"]).matches("Alpha", &[]));
+        assert!(subscription(&["Alpha"], &["ln"]).matches("Alpha", &manga));
+        assert!(!subscription(&["Alpha"], &["ln"]).matches("Beta", &manga));
+    }
+
+    #[test]
+    fn test_resolve_env() {
+        // The process environment is shared by tests running in parallel
+        let vars = HashMap::from([("PROCESSED", "https://discord.com/api/webhooks/1/a")]);
+        let lookup = |var: &str| {
+            vars.get(var)
+                .map(|value| value.to_string())
+                .ok_or(env::VarError::NotPresent)
+        };
+
+        assert_eq!(
+            resolve_with("Webhook", "$PROCESSED", lookup).unwrap(),
+            "https://discord.com/api/webhooks/1/a"
+        );
+        assert_eq!(
+            resolve_with("Webhook", "https://example.com/hook", lookup).unwrap(),
+            "https://example.com/hook"
+        );
+        let e = resolve_with("Webhook", "$DAILY", lookup).unwrap_err();
+        assert_eq!(e, "Webhook env DAILY: environment variable not found");
+
+        let target = Target {
+            webhook: "https://example.com/hook".to_string(),
+            roles: vec!["1".to_string(), "2".to_string()],
+        };
+        assert_eq!(target.url().unwrap(), "https://example.com/hook");
+        assert_eq!(target.mention(), "<@&1> <@&2>");
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    config::{app::AppConfig, notify::NotifyConfig},
+    notifier::{Event, Notifiers},
+};
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+/// Where files are read and written. Relative config paths are resolved
+/// against `root`, the working directory by default
+#[derive(Debug, Clone, Default)]
+pub struct Storage {
+    root: PathBuf,
+}
+
+impl Storage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Storage { root: root.into() }
+    }
+
+    /// `path` from the config, resolved against the root
+    pub fn path(&self, path: &str) -> String {
+        self.root.join(path).to_string_lossy().into_owned()
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        Path::new(&self.path(path)).exists()
+    }
+
+    /// A storage rooted in a fresh [`TempDir`], keep the guard for as long
+    /// as the test uses it
+    #[cfg(test)]
+    pub(crate) fn temp(name: &str) -> (Storage, TempDir) {
+        let dir = TempDir::new(name);
+        (Storage::new(dir.path()), dir)
+    }
+}
+
+/// A directory of its own for a test, removed on drop so a failing
+/// assertion doesn't leave it behind
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// `vbt_<name>_<pid>` in the system temp dir, emptied but not created,
+    /// the writers under test create it
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("vbt_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// `file` in the directory, as the string paths in the config are
+    pub(crate) fn file(&self, file: &str) -> String {
+        self.0.join(file).to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Everything a command needs: the settings, one shared HTTP client, where
+/// files live and who gets notified. Passed explicitly instead of globals,
+/// so tests and several runs in one process each get their own
+pub struct AppContext {
+    pub config: AppConfig,
+    pub notify: NotifyConfig,
+    pub client: Client,
+    pub storage: Storage,
+    notifiers: Mutex<Notifiers>,
+}
+
+impl AppContext {
+    /// A context without notifiers, see [`AppContext::with_notifiers`]
+    pub fn new(config: AppConfig, notify: NotifyConfig) -> Self {
+        AppContext {
+            config,
+            notify,
+            client: Client::new(),
+            storage: Storage::default(),
+            notifiers: Mutex::new(Notifiers::default()),
+        }
+    }
+
+    /// Resolve every path against `storage`
+    pub fn storage(mut self, storage: Storage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// A context for `config` on [`Storage::temp`]
+    #[cfg(test)]
+    pub(crate) fn temp(name: &str, config: AppConfig) -> (Self, TempDir) {
+        let (storage, dir) = Storage::temp(name);
+        let ctx = AppContext::new(config, NotifyConfig::default()).storage(storage);
+        (ctx, dir)
+    }
+
+    /// Build the backends enabled in `notify`, none unless `notify.enabled`
+    pub fn with_notifiers(mut self) -> Result<Self, String> {
+        self.notifiers = Mutex::new(Notifiers::from_context(&self)?);
+        Ok(self)
+    }
+
+    /// Resolve `path` from the config against the storage root
+    pub fn path(&self, path: &str) -> String {
+        self.storage.path(path)
+    }
+
+    /// Send `event` to every notifier, see [`Notifiers::emit`]
+    pub async fn emit(&self, event: Event<'_>) -> Vec<String> {
+        self.notifiers.lock().await.emit(event).await
+    }
+
+    pub async fn has_notifiers(&self) -> bool {
+        !self.notifiers.lock().await.is_empty()
+    }
+
+    /// Persist notifier state, e.g. dead letters and the email digest
+    pub async fn finish(&self) -> Result<(), String> {
+        self.notifiers.lock().await.finish().await
+    }
+}
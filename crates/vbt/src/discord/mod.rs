@@ -1,2 +1,4 @@
+pub mod queue;
 pub mod send;
+pub mod status;
 pub mod wh;
@@ -0,0 +1,601 @@
+use crate::{
+    config::notify::resolve_webhook,
+    discord::send::{WebhookMessage, WebhookPayload},
+    utils::fs::write_atomic,
+};
+use reqwest::{header::HeaderMap, Client, Method, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+
+/// Attempts per message before it goes to the dead-letter file
+const MAX_ATTEMPTS: u32 = 5;
+/// Backoff for the first retry after a 5xx or network error, doubled each time
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound for any single wait, Discord sometimes asks for minutes
+const MAX_WAIT: Duration = Duration::from_secs(60);
+/// Runs a dead letter is tried on before it is parked
+const MAX_DEAD_LETTER_RUNS: u32 = 5;
+
+/// A message that couldn't be delivered, retried on the next run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetter {
+    /// Webhook reference as written in `notify.json` when it is a `$VAR`,
+    /// otherwise `sha256:<hex>` of the url, the file is committed and must
+    /// not hold a secret url
+    pub webhook: String,
+    pub payload: WebhookPayload,
+    pub error: String,
+    /// Runs that failed to deliver it
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub first_failed_at: String,
+    pub failed_at: String,
+    /// Given up on after [`MAX_DEAD_LETTER_RUNS`] runs or a 4xx other than
+    /// 429. Parked letters stay in the file to be looked at, never retried
+    #[serde(default)]
+    pub parked: bool,
+}
+
+/// Why a request failed
+struct Failure {
+    error: String,
+    /// Retrying the same request can't help, e.g. a 400 or a deleted webhook
+    permanent: bool,
+}
+
+impl Failure {
+    fn transient(error: String) -> Self {
+        Failure {
+            error,
+            permanent: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RateLimit {
+    remaining: Option<u32>,
+    reset_at: Option<Instant>,
+}
+
+/// Delivers webhook messages one after another, waiting out each webhook's
+/// rate limit and retrying 429 and 5xx responses
+pub struct DeliveryQueue {
+    client: Client,
+    limits: HashMap<String, RateLimit>,
+    /// The webhooks of `notify.json`, to find the url of a hashed dead letter
+    webhooks: Vec<String>,
+    dead_letter_path: String,
+    dead_letters: Vec<DeadLetter>,
+    /// Whether `dead_letters` differs from the file
+    dirty: bool,
+}
+
+#[derive(Deserialize)]
+struct RateLimitBody {
+    retry_after: f64,
+}
+
+impl DeliveryQueue {
+    pub fn new(dead_letter_path: impl Into<String>) -> Self {
+        DeliveryQueue {
+            client: Client::new(),
+            limits: HashMap::new(),
+            webhooks: Vec::new(),
+            dead_letter_path: dead_letter_path.into(),
+            dead_letters: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    /// Share `client`, and its connection pool, with the rest of the app
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// The webhook references dead letters may come back to, see
+    /// [`DeadLetter::webhook`]
+    pub fn webhooks(mut self, webhooks: impl IntoIterator<Item = String>) -> Self {
+        self.webhooks = webhooks.into_iter().collect();
+        self
+    }
+
+    fn load_dead_letters(&self) -> Result<Vec<DeadLetter>, String> {
+        let file = match File::open(&self.dead_letter_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to open {}: {}", self.dead_letter_path, e)),
+        };
+        serde_json::from_reader(file)
+            .map_err(|e| format!("Failed to parse {}: {}", self.dead_letter_path, e))
+    }
+
+    /// Try to deliver everything left in the dead-letter file by earlier runs.
+    /// Returns how many messages went through
+    pub async fn retry_dead_letters(&mut self) -> Result<usize, String> {
+        let pending = self.load_dead_letters()?;
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        // Whatever fails again is added back by `deliver`
+        self.dirty = true;
+
+        let mut delivered = 0;
+        for mut letter in pending {
+            if letter.parked {
+                self.dead_letters.push(letter);
+                continue;
+            }
+            // Letters written before urls were hashed still hold the url
+            let webhook = if letter.webhook.starts_with(HASH_PREFIX) {
+                self.webhooks
+                    .iter()
+                    .find(|webhook| stored_webhook(webhook) == letter.webhook)
+                    .cloned()
+            } else {
+                Some(letter.webhook.clone())
+            };
+            let Some(webhook) = webhook else {
+                letter.error = "the webhook is no longer in notify.json".to_string();
+                letter.parked = true;
+                self.dead_letters.push(letter);
+                continue;
+            };
+            if self
+                .deliver_again(&webhook, letter.payload.clone(), Some(&letter))
+                .await
+                .is_some()
+            {
+                delivered += 1;
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Deliver `payload` to `webhook`, split to fit Discord's limits.
+    /// Returns the created message ids, or `None` if some part was
+    /// dead-lettered instead
+    pub async fn deliver(&mut self, webhook: &str, payload: WebhookPayload) -> Option<Vec<String>> {
+        self.deliver_again(webhook, payload, None).await
+    }
+
+    /// [`deliver`](Self::deliver), carrying over the attempts of `earlier`
+    /// when it is a dead letter being retried
+    async fn deliver_again(
+        &mut self,
+        webhook: &str,
+        payload: WebhookPayload,
+        earlier: Option<&DeadLetter>,
+    ) -> Option<Vec<String>> {
+        let url = match resolve_webhook(webhook) {
+            Ok(url) => url,
+            Err(e) => {
+                // The variable may be set by the next run
+                self.dead_letter(webhook, payload, Failure::transient(e), earlier);
+                return None;
+            }
+        };
+
+        let mut ids = Vec::new();
+        let mut all_delivered = true;
+        for message in payload.split() {
+            match self.post(&url, &message).await {
+                Ok(message) => ids.push(message.id),
+                Err(failure) => {
+                    eprintln!("Failed to deliver Discord message: {}", failure.error);
+                    self.dead_letter(webhook, message, failure, earlier);
+                    all_delivered = false;
+                }
+            }
+        }
+
+        all_delivered.then_some(ids)
+    }
+
+    fn dead_letter(
+        &mut self,
+        webhook: &str,
+        payload: WebhookPayload,
+        failure: Failure,
+        earlier: Option<&DeadLetter>,
+    ) {
+        let now = chrono::Utc::now().to_rfc3339();
+        let attempts = earlier.map_or(0, |letter| letter.attempts) + 1;
+        let first_failed_at = match earlier {
+            // Letters written before attempts were counted
+            Some(letter) if letter.first_failed_at.is_empty() => letter.failed_at.clone(),
+            Some(letter) => letter.first_failed_at.clone(),
+            None => now.clone(),
+        };
+        let parked = failure.permanent || attempts >= MAX_DEAD_LETTER_RUNS;
+        if parked {
+            eprintln!(
+                "Gave up on a Discord message after {} run(s), it is kept in {}",
+                attempts, self.dead_letter_path
+            );
+        }
+
+        self.dead_letters.push(DeadLetter {
+            webhook: stored_webhook(webhook),
+            payload,
+            error: failure.error,
+            attempts,
+            first_failed_at,
+            failed_at: now,
+            parked,
+        });
+        self.dirty = true;
+    }
+
+    /// Replace the embeds of a message sent earlier through `webhook`.
+    /// Edits are not dead-lettered, a later edit supersedes them anyway
+    pub async fn edit(
+        &mut self,
+        webhook: &str,
+        message_id: &str,
+        payload: WebhookPayload,
+    ) -> Result<(), String> {
+        let url = resolve_webhook(webhook)?;
+        let url = edit_url(&url, message_id);
+        // An edit can't be split over several messages, keep the first part
+        let message = payload.split().into_iter().next().unwrap_or_default();
+
+        self.request(Method::PATCH, &url, &message)
+            .await
+            .map(|_| ())
+            .map_err(|failure| failure.error)
+    }
+
+    /// Messages that could not be delivered so far
+    pub fn dead_letters(&self) -> &[DeadLetter] {
+        &self.dead_letters
+    }
+
+    /// Persist undelivered messages for the next run
+    pub fn finish(&self) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let data = serde_json::to_vec_pretty(&self.dead_letters)
+            .map_err(|e| format!("Failed to serialize dead letters: {}", e))?;
+        write_atomic(&self.dead_letter_path, &data)
+    }
+
+    /// Execute the webhook with `?wait=true`, retrying as needed
+    async fn post(
+        &mut self,
+        url: &str,
+        payload: &WebhookPayload,
+    ) -> Result<WebhookMessage, Failure> {
+        self.request(Method::POST, url, payload).await
+    }
+
+    async fn request(
+        &mut self,
+        method: Method,
+        url: &str,
+        payload: &WebhookPayload,
+    ) -> Result<WebhookMessage, Failure> {
+        let mut last_error = String::new();
+        // Edits share the rate limit of the webhook they belong to
+        let bucket = url.split("/messages/").next().unwrap_or(url).to_string();
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            self.wait_for_limit(&bucket).await;
+
+            let mut request = self.client.request(method.clone(), url).json(payload);
+            if method == Method::POST {
+                request = request.query(&[("wait", "true")]);
+            }
+            let response = request.send().await;
+
+            let response = match response {
+                Ok(response) => response,
+                Err(e) => {
+                    // The error would name the url, and with it the token
+                    last_error = format!("request failed: {}", e.without_url());
+                    tokio::time::sleep(backoff(attempt)).await;
+                    continue;
+                }
+            };
+
+            self.update_limit(&bucket, response.headers());
+            let status = response.status();
+
+            if status.is_success() {
+                return response
+                    .json::<WebhookMessage>()
+                    .await
+                    .map_err(|e| Failure::transient(format!("invalid response: {}", e)));
+            }
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let wait = retry_after(response).await;
+                last_error = format!("rate limited for {:?}", wait);
+                tokio::time::sleep(wait).await;
+            } else if status.is_server_error() {
+                last_error = format!("Discord returned {}", status);
+                tokio::time::sleep(backoff(attempt)).await;
+            } else {
+                // Other 4xx won't get better by retrying
+                let body = response.text().await.unwrap_or_default();
+                return Err(Failure {
+                    error: format!("Discord returned {}: {}", status, body),
+                    permanent: true,
+                });
+            }
+        }
+
+        Err(Failure::transient(format!(
+            "gave up after {} attempts: {}",
+            MAX_ATTEMPTS, last_error
+        )))
+    }
+
+    async fn wait_for_limit(&self, url: &str) {
+        if let Some(RateLimit {
+            remaining: Some(0),
+            reset_at: Some(reset_at),
+        }) = self.limits.get(url)
+        {
+            let now = Instant::now();
+            if *reset_at > now {
+                tokio::time::sleep((*reset_at - now).min(MAX_WAIT)).await;
+            }
+        }
+    }
+
+    fn update_limit(&mut self, url: &str, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        let limit = self.limits.entry(url.to_string()).or_default();
+
+        if let Some(remaining) = header("x-ratelimit-remaining").and_then(|v| v.parse().ok()) {
+            limit.remaining = Some(remaining);
+        }
+        if let Some(reset_after) = header("x-ratelimit-reset-after").and_then(|v| v.parse().ok()) {
+            limit.reset_at = Some(Instant::now() + seconds(reset_after));
+        }
+    }
+}
+
+/// Marks a hashed webhook url in [`DeadLetter::webhook`]
+const HASH_PREFIX: &str = "sha256:";
+
+/// How `webhook` is written in the dead-letter file: a `$VAR` as is, a
+/// literal url as the hash of it
+fn stored_webhook(webhook: &str) -> String {
+    if webhook.starts_with('$') {
+        webhook.to_string()
+    } else {
+        format!("{}{}", HASH_PREFIX, hex::encode(Sha256::digest(webhook)))
+    }
+}
+
+/// `https://discord.com/api/webhooks/{id}/{token}/messages/{message_id}`,
+/// keeping any query string of the webhook url (e.g. `thread_id`)
+fn edit_url(url: &str, message_id: &str) -> String {
+    match url.split_once('?') {
+        Some((base, query)) => format!(
+            "{}/messages/{}?{}",
+            base.trim_end_matches('/'),
+            message_id,
+            query
+        ),
+        None => format!("{}/messages/{}", url.trim_end_matches('/'), message_id),
+    }
+}
+
+fn seconds(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs)
+        .unwrap_or(MAX_WAIT)
+        .min(MAX_WAIT)
+}
+
+fn backoff(attempt: u32) -> Duration {
+    (BASE_BACKOFF * 2u32.pow(attempt - 1)).min(MAX_WAIT)
+}
+
+/// How long a 429 asks us to wait, from the body or the `Retry-After` header
+async fn retry_after(response: Response) -> Duration {
+    let header = response
+        .headers()
+        .get("retry-after")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<f64>().ok());
+
+    match response.json::<RateLimitBody>().await {
+        Ok(body) => seconds(body.retry_after),
+        Err(_) => header.map(seconds).unwrap_or(BASE_BACKOFF),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::TempDir;
+    use wiremock::{
+        matchers::{method, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    /// A dead letter file in a directory of its own
+    fn dead_letter_path(name: &str) -> (String, TempDir) {
+        let dir = TempDir::new(&format!("queue_{}", name));
+        (dir.file("dead_letters.json"), dir)
+    }
+
+    #[tokio::test]
+    async fn test_retries_after_rate_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .set_body_json(serde_json::json!({ "retry_after": 0.05, "global": false })),
+            )
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(query_param("wait", "true"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "id": "42", "channel_id": "1" })),
+            )
+            .mount(&server)
+            .await;
+
+        let mut queue = DeliveryQueue::new(dead_letter_path("rate_limit").0);
+        let ids = queue
+            .deliver(&server.uri(), WebhookPayload::new("hello"))
+            .await;
+
+        assert_eq!(ids, Some(vec!["42".to_string()]));
+        assert!(queue.dead_letters().is_empty());
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_edit_patches_message() {
+        let server = MockServer::start().await;
+        Mock::given(method("PATCH"))
+            .and(wiremock::matchers::path("/messages/42"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "id": "42", "channel_id": "1" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut queue = DeliveryQueue::new(dead_letter_path("edit").0);
+        queue
+            .edit(&server.uri(), "42", WebhookPayload::new("edited"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_are_saved_and_retried() {
+        let (path, _dir) = dead_letter_path("dead_letter");
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "id": "7", "channel_id": "1" })),
+            )
+            .mount(&server)
+            .await;
+
+        // A variable that isn't set may be by the next run, no env var is
+        // ever set here as tests share the process environment
+        let unset = "$VBT_TEST_DEAD_LETTER_UNSET";
+        let mut queue = DeliveryQueue::new(&path);
+        assert_eq!(
+            queue.deliver(unset, WebhookPayload::new("unset")).await,
+            None
+        );
+        assert_eq!(queue.dead_letters()[0].attempts, 1);
+        assert!(!queue.dead_letters()[0].parked);
+        // A message Discord failed on
+        let failure = Failure::transient("Discord returned 503".to_string());
+        queue.dead_letter(&server.uri(), WebhookPayload::new("hello"), failure, None);
+        queue.finish().unwrap();
+
+        let mut next_run = DeliveryQueue::new(&path).webhooks([server.uri()]);
+        assert_eq!(next_run.retry_dead_letters().await, Ok(1));
+        next_run.finish().unwrap();
+
+        let left: Vec<DeadLetter> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].webhook, unset);
+        assert_eq!(left[0].attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_literal_webhooks_are_hashed() {
+        let (path, _dir) = dead_letter_path("hashed");
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "id": "7", "channel_id": "1" })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let webhook = format!("{}/api/webhooks/1/secret-token", server.uri());
+
+        let mut queue = DeliveryQueue::new(&path);
+        let failure = Failure::transient("Discord returned 503".to_string());
+        queue.dead_letter(&webhook, WebhookPayload::new("hello"), failure, None);
+        queue.finish().unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("secret-token"));
+        assert!(saved.contains(&stored_webhook(&webhook)));
+
+        // Dropped from notify.json, nowhere to send it
+        let mut next_run = DeliveryQueue::new(&path);
+        assert_eq!(next_run.retry_dead_letters().await, Ok(0));
+        assert!(next_run.dead_letters()[0].parked);
+
+        // Found again among the configured webhooks
+        let mut next_run = DeliveryQueue::new(&path).webhooks([webhook]);
+        assert_eq!(next_run.retry_dead_letters().await, Ok(1));
+        assert!(next_run.dead_letters().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_are_parked() {
+        let (path, _dir) = dead_letter_path("parked");
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("bad request"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // A 4xx other than 429 is parked right away
+        let mut queue = DeliveryQueue::new(&path);
+        queue
+            .deliver(&server.uri(), WebhookPayload::new("rejected"))
+            .await;
+        // Anything else once it failed on enough runs
+        let letter = DeadLetter {
+            webhook: "$VBT_TEST_PARKED_UNSET".to_string(),
+            payload: WebhookPayload::new("unset"),
+            error: "Webhook env VBT_TEST_PARKED_UNSET: not set".to_string(),
+            attempts: MAX_DEAD_LETTER_RUNS - 1,
+            first_failed_at: "2024-11-01T00:00:00+00:00".to_string(),
+            failed_at: "2024-11-04T00:00:00+00:00".to_string(),
+            parked: false,
+        };
+        queue.dead_letters.push(letter);
+        queue.dirty = true;
+        queue.finish().unwrap();
+
+        let mut next_run = DeliveryQueue::new(&path);
+        assert_eq!(next_run.retry_dead_letters().await, Ok(0));
+        let letters = next_run.dead_letters();
+        assert_eq!(letters.len(), 2);
+        assert!(letters.iter().all(|letter| letter.parked));
+        assert_eq!(letters[0].attempts, 1);
+        assert!(letters[0].error.starts_with("Discord returned 400"));
+        assert_eq!(letters[1].attempts, MAX_DEAD_LETTER_RUNS);
+        assert_eq!(letters[1].first_failed_at, "2024-11-01T00:00:00+00:00");
+
+        // Parked letters are kept but never sent again
+        next_run.finish().unwrap();
+        let mut later = DeliveryQueue::new(&path);
+        assert_eq!(later.retry_dead_letters().await, Ok(0));
+        assert_eq!(later.dead_letters().len(), 2);
+    }
+}
@@ -1,95 +1,436 @@
 use reqwest::Client;
-use serde_json::json;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
-#[derive(Default)]
+
+/// Colour used when an embed doesn't set one
+pub const DEFAULT_COLOR: u32 = 0x00ff00;
+
+// https://discord.com/developers/docs/resources/message#embed-object-embed-limits
+pub const MAX_CONTENT: usize = 2000;
+pub const MAX_USERNAME: usize = 80;
+pub const MAX_EMBEDS: usize = 10;
+pub const MAX_FIELDS: usize = 25;
+pub const MAX_TITLE: usize = 256;
+pub const MAX_DESCRIPTION: usize = 4096;
+pub const MAX_FIELD_NAME: usize = 256;
+pub const MAX_FIELD_VALUE: usize = 1024;
+pub const MAX_FOOTER: usize = 2048;
+pub const MAX_AUTHOR_NAME: usize = 256;
+/// Total characters of all embeds in one message
+pub const MAX_EMBED_TOTAL: usize = 6000;
+
+/// Body of an execute webhook request
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct WebhookPayload {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<DiscordEmbed>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_mentions: Option<AllowedMentions>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
 pub struct DiscordEmbed {
     pub title: String,
     pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// ISO 8601 timestamp shown next to the footer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<EmbedAuthor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub fields: Option<Vec<EmbedField>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub footer: Option<EmbedFooter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<EmbedImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub thumbnail: Option<EmbedThumbnail>,
 }
 
-#[derive(Default)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
 pub struct EmbedField {
     pub name: String,
     pub value: String,
     pub inline: bool,
 }
 
-#[derive(Default)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
 pub struct EmbedFooter {
     pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub icon_url: Option<String>,
 }
 
-#[derive(Default)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct EmbedAuthor {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
 pub struct EmbedImage {
     pub url: String,
 }
 
-#[derive(Default)]
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
 pub struct EmbedThumbnail {
     pub url: String,
 }
 
+/// Which mentions in `content` actually ping
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct AllowedMentions {
+    /// Any of "roles", "users" and "everyone"
+    pub parse: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+}
+
+impl AllowedMentions {
+    /// Only ping the given role ids
+    pub fn roles(roles: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        AllowedMentions {
+            parse: Vec::new(),
+            roles: roles.into_iter().map(Into::into).collect(),
+            users: Vec::new(),
+        }
+    }
+}
+
+/// The message Discord returns when executing with `?wait=true`
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookMessage {
+    pub id: String,
+    pub channel_id: String,
+}
+
+/// Cut `s` to at most `max` characters, marking the cut with an ellipsis
+fn truncate(s: &mut String, max: usize) {
+    if s.chars().count() > max {
+        let cut: String = s.chars().take(max.saturating_sub(1)).collect();
+        *s = cut + "…";
+    }
+}
+
+impl DiscordEmbed {
+    /// Characters that count towards [`MAX_EMBED_TOTAL`]
+    fn len(&self) -> usize {
+        self.title.chars().count()
+            + self.description.chars().count()
+            + self.fields_len()
+            + self.footer.as_ref().map_or(0, |f| f.text.chars().count())
+            + self.author.as_ref().map_or(0, |a| a.name.chars().count())
+    }
+
+    fn truncate(&mut self) {
+        truncate(&mut self.title, MAX_TITLE);
+        truncate(&mut self.description, MAX_DESCRIPTION);
+        for field in self.fields.iter_mut().flatten() {
+            truncate(&mut field.name, MAX_FIELD_NAME);
+            truncate(&mut field.value, MAX_FIELD_VALUE);
+        }
+        if let Some(footer) = &mut self.footer {
+            truncate(&mut footer.text, MAX_FOOTER);
+        }
+        if let Some(author) = &mut self.author {
+            truncate(&mut author.name, MAX_AUTHOR_NAME);
+        }
+
+        // Title, footer and author fit with room to spare once truncated,
+        // the description gives way when the text outside the fields is
+        // still over the total
+        let fields_len = self.fields_len();
+        let text_len = self.len() - fields_len;
+        if text_len > MAX_EMBED_TOTAL {
+            let description_len = self.description.chars().count();
+            truncate(
+                &mut self.description,
+                description_len - (text_len - MAX_EMBED_TOTAL),
+            );
+        }
+    }
+
+    fn fields_len(&self) -> usize {
+        self.fields.iter().flatten().fold(0, |acc, f| {
+            acc + f.name.chars().count() + f.value.chars().count()
+        })
+    }
+
+    /// Split into embeds of at most [`MAX_FIELDS`] fields that each fit in a
+    /// message, the continuations keep the title but not the description
+    fn split_fields(mut self) -> Vec<DiscordEmbed> {
+        let fields = self.fields.take().unwrap_or_default();
+        let mut continuation = DiscordEmbed {
+            title: format!("{} (cont.)", self.title),
+            description: String::new(),
+            ..self.clone()
+        };
+        truncate(&mut continuation.title, MAX_TITLE);
+        let mut embeds = vec![self];
+        let mut len = embeds[0].len();
+
+        for field in fields {
+            let field_len = field.name.chars().count() + field.value.chars().count();
+            let current = embeds.last_mut().expect("embeds is never empty");
+            let count = current.fields.as_ref().map_or(0, Vec::len);
+            // The first embed may have no room left for even one field next
+            // to its description, a continuation always has
+            let movable = count > 0 || embeds.len() == 1;
+            if movable && (count == MAX_FIELDS || len + field_len > MAX_EMBED_TOTAL) {
+                embeds.push(continuation.clone());
+                len = continuation.len();
+            }
+            len += field_len;
+            embeds
+                .last_mut()
+                .expect("embeds is never empty")
+                .fields
+                .get_or_insert_with(Vec::new)
+                .push(field);
+        }
+
+        embeds
+    }
+}
+
+impl WebhookPayload {
+    pub fn new(content: impl Into<String>) -> Self {
+        WebhookPayload {
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn embed(mut self, embed: DiscordEmbed) -> Self {
+        self.embeds.push(embed);
+        self
+    }
+
+    /// Fit the payload into Discord's limits: strings are truncated and
+    /// embeds are spread over as many messages as needed. Only the first
+    /// message keeps the content
+    pub fn split(mut self) -> Vec<WebhookPayload> {
+        truncate(&mut self.content, MAX_CONTENT);
+        if let Some(username) = &mut self.username {
+            truncate(username, MAX_USERNAME);
+        }
+
+        let embeds: Vec<DiscordEmbed> = std::mem::take(&mut self.embeds)
+            .into_iter()
+            .flat_map(|mut embed| {
+                embed.truncate();
+                embed.split_fields()
+            })
+            .collect();
+
+        let template = WebhookPayload {
+            content: String::new(),
+            embeds: Vec::new(),
+            ..self.clone()
+        };
+        let mut messages = vec![self];
+        let mut total = 0;
+
+        for embed in embeds {
+            let len = embed.len();
+            let current = messages.last_mut().expect("messages is never empty");
+            if current.embeds.len() == MAX_EMBEDS
+                || (!current.embeds.is_empty() && total + len > MAX_EMBED_TOTAL)
+            {
+                messages.push(template.clone());
+                total = 0;
+            }
+            total += len;
+            messages
+                .last_mut()
+                .expect("messages is never empty")
+                .embeds
+                .push(embed);
+        }
+
+        messages
+    }
+}
+
+/// Execute a webhook with `payload` as is. With `wait` Discord returns the
+/// created message. Non-success responses are turned into errors
+pub async fn execute(
+    client: &Client,
+    url: &str,
+    payload: &WebhookPayload,
+    wait: bool,
+) -> Result<Option<WebhookMessage>, Box<dyn Error>> {
+    let mut request = client.post(url).json(payload);
+    if wait {
+        request = request.query(&[("wait", "true")]);
+    }
+
+    let response = request.send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Discord webhook returned {}: {}", status, body).into());
+    }
+
+    if wait {
+        Ok(Some(response.json::<WebhookMessage>().await?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Send `payload` through `client`, usually the shared
+/// [`AppContext::client`](crate::context::AppContext::client), split to
+/// fit Discord's limits, and return the ids of the created messages
+pub async fn send_payload(
+    client: &Client,
+    url: &str,
+    payload: WebhookPayload,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut ids = Vec::new();
+
+    for message in payload.split() {
+        if let Some(message) = execute(client, url, &message, true).await? {
+            ids.push(message.id);
+        }
+    }
+
+    Ok(ids)
+}
+
 pub async fn send(
+    client: &Client,
     url: String,
     content: String,
     embed: Option<DiscordEmbed>,
 ) -> Result<(), Box<dyn Error>> {
-    let client = Client::new();
-
-    let mut payload = json!({
-        "content": content
-    });
-
-    if let Some(embed) = embed {
-        let mut embed_json = json!({
-            "title": embed.title,
-            "description": embed.description,
-            "color": 0x00ff00
-        });
-
-        if let Some(fields) = embed.fields {
-            embed_json["fields"] = json!(fields
-                .iter()
-                .map(|f| {
-                    json!({
-                        "name": f.name,
-                        "value": f.value,
-                        "inline": f.inline
-                    })
-                })
-                .collect::<Vec<_>>());
-        }
+    let mut payload = WebhookPayload::new(content);
+    if let Some(mut embed) = embed {
+        embed.color.get_or_insert(DEFAULT_COLOR);
+        payload = payload.embed(embed);
+    }
 
-        if let Some(footer) = embed.footer {
-            let mut footer_json = json!({
-                "text": footer.text
-            });
-            if let Some(icon_url) = footer.icon_url {
-                footer_json["icon_url"] = json!(icon_url);
-            }
-            embed_json["footer"] = footer_json;
-        }
+    send_payload(client, &url, payload).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if let Some(image) = embed.image {
-            embed_json["image"] = json!({
-                "url": image.url
-            });
+    fn field(i: usize) -> EmbedField {
+        EmbedField {
+            name: format!("Book {}", i),
+            value: "x".repeat(200),
+            inline: false,
         }
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut s = "ab".repeat(10);
+        truncate(&mut s, 5);
+        assert_eq!(s, "abab…");
+        assert_eq!(s.chars().count(), 5);
+    }
+
+    #[test]
+    fn test_split_fields_and_total_length() {
+        let embed = DiscordEmbed {
+            title: "Entry".to_string(),
+            description: "d".repeat(5000),
+            fields: Some((0..60).map(field).collect()),
+            ..Default::default()
+        };
+        let messages = WebhookPayload::new("<@&1>").embed(embed).split();
+
+        let embeds: Vec<_> = messages.iter().flat_map(|m| &m.embeds).collect();
+        let fields: usize = embeds
+            .iter()
+            .map(|e| e.fields.as_ref().unwrap().len())
+            .sum();
+        assert_eq!(fields, 60);
+        assert_eq!(embeds[0].description.chars().count(), MAX_DESCRIPTION);
+        assert_eq!(embeds[1].title, "Entry (cont.)");
+        assert!(embeds
+            .iter()
+            .all(|e| e.fields.as_ref().unwrap().len() <= MAX_FIELDS));
 
-        if let Some(thumbnail) = embed.thumbnail {
-            embed_json["thumbnail"] = json!({
-                "url": thumbnail.url
-            });
+        assert_eq!(messages[0].content, "<@&1>");
+        assert!(messages[1..].iter().all(|m| m.content.is_empty()));
+        for message in &messages {
+            let total: usize = message.embeds.iter().map(DiscordEmbed::len).sum();
+            assert!(total <= MAX_EMBED_TOTAL);
+            assert!(message.embeds.len() <= MAX_EMBEDS);
         }
-        payload["embeds"] = json!([embed_json]);
     }
 
-    client.post(&url).json(&payload).send().await?;
+    #[test]
+    fn test_serialize_skips_empty() {
+        let payload = WebhookPayload {
+            allowed_mentions: Some(AllowedMentions::roles(["1"])),
+            ..WebhookPayload::new("hi")
+        };
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "content": "hi",
+                "allowed_mentions": { "parse": [], "roles": ["1"] }
+            })
+        );
+    }
 
-    Ok(())
+    #[test]
+    fn test_oversized_text() {
+        let embed = DiscordEmbed {
+            title: "t".repeat(300),
+            description: "d".repeat(5000),
+            footer: Some(EmbedFooter {
+                text: "f".repeat(3000),
+                ..Default::default()
+            }),
+            author: Some(EmbedAuthor {
+                name: "a".repeat(300),
+                ..Default::default()
+            }),
+            fields: Some((0..3).map(field).collect()),
+            ..Default::default()
+        };
+        let messages = WebhookPayload::new("").embed(embed).split();
+
+        let embeds: Vec<_> = messages.iter().flat_map(|m| &m.embeds).collect();
+        assert_eq!(embeds[0].len(), MAX_EMBED_TOTAL);
+        assert!(embeds[0].description.ends_with('…'));
+        assert!(embeds[0].fields.is_none());
+        assert_eq!(embeds[1].fields.as_ref().unwrap().len(), 3);
+        assert_eq!(embeds[1].title.chars().count(), MAX_TITLE);
+        for message in &messages {
+            let total: usize = message.embeds.iter().map(DiscordEmbed::len).sum();
+            assert!(total <= MAX_EMBED_TOTAL);
+        }
+    }
 }
@@ -0,0 +1,173 @@
+use super::send::{DiscordEmbed, EmbedFooter, DEFAULT_COLOR};
+use crate::{notifier::RunStats, utils::time::generate_time};
+use chrono::Utc;
+use chrono_tz::Tz;
+use std::time::Duration;
+
+/// Colour of the summary when some entries failed
+const FAILED_COLOR: u32 = 0xff0000;
+
+/// How a watchlist entry went during a run
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryStatus {
+    /// Fetched, with the number of new or changed books
+    Ok(usize),
+    /// The registry has no rows for the entry
+    Empty,
+    Failed(String),
+}
+
+/// The daily status message, posted once when a run starts and edited in
+/// place as entries complete
+#[derive(Debug)]
+pub struct StatusMessage {
+    pub id: i64,
+    /// Discord id of the posted message, `None` until it was delivered
+    pub message_id: Option<String>,
+    total: usize,
+    entries: Vec<(String, EntryStatus)>,
+    /// Zone of the footer time
+    tz: Tz,
+}
+
+impl StatusMessage {
+    pub fn new(id: i64, tz: Tz) -> Self {
+        StatusMessage {
+            id,
+            message_id: None,
+            total: 0,
+            entries: Vec::new(),
+            tz,
+        }
+    }
+
+    /// Number of entries the run will go through
+    pub fn set_total(&mut self, total: usize) {
+        self.total = total;
+    }
+
+    pub fn record(&mut self, name: impl Into<String>, status: EntryStatus) {
+        self.entries.push((name.into(), status));
+    }
+
+    /// Embed shown while the run is going on
+    pub fn progress(&self) -> DiscordEmbed {
+        let title = if self.total == 0 {
+            "A daily worker has started".to_string()
+        } else {
+            format!(
+                "A daily worker is running ({}/{})",
+                self.entries.len(),
+                self.total
+            )
+        };
+
+        self.embed(title, self.lines(), DEFAULT_COLOR)
+    }
+
+    /// Embed replacing the progress once the run is over. `error` is the
+    /// reason the run stopped early, if it did
+    pub fn summary(&self, stats: &RunStats, next_run: i64, error: Option<&str>) -> DiscordEmbed {
+        let mut lines = vec![format!(
+            "Processed: **{}** | New: **{}** | Failed: **{}** | Duration: {}",
+            stats.processed,
+            stats.new_books,
+            stats.failed,
+            format_duration(stats.duration)
+        )];
+        if let Some(error) = error {
+            lines.push(format!("Stopped early: {}", error));
+        }
+        // <t:TIME:R> Relative
+        lines.push(format!("Next automatic worker is: <t:{}:R>", next_run));
+        lines.push(String::new());
+        lines.push(self.lines());
+
+        let color = if error.is_some() || stats.failed > 0 {
+            FAILED_COLOR
+        } else {
+            DEFAULT_COLOR
+        };
+
+        self.embed("Finished".to_string(), lines.join("\n"), color)
+    }
+
+    fn lines(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(name, status)| match status {
+                EntryStatus::Ok(0) => format!("✅ {}: no new books", name),
+                EntryStatus::Ok(new) => format!("🆕 {}: {} new or updated", name, new),
+                EntryStatus::Empty => format!("⚪ {}: empty", name),
+                EntryStatus::Failed(e) => format!("❌ {}: {}", name, e),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn embed(&self, title: String, lines: String, color: u32) -> DiscordEmbed {
+        DiscordEmbed {
+            title,
+            description: format!("Id: {}\n{}", self.id, lines).trim_end().to_string(),
+            color: Some(color),
+            timestamp: Some(Utc::now().to_rfc3339()),
+            footer: Some(EmbedFooter {
+                text: format!("VBT - {}", generate_time(self.tz)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// `1h 02m 03s`, `2m 03s` or `3s`
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}h {:02}m {:02}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_and_summary() {
+        let mut status = StatusMessage::new(7, Tz::Asia__Ho_Chi_Minh);
+        assert_eq!(status.progress().title, "A daily worker has started");
+
+        status.set_total(3);
+        status.record("A", EntryStatus::Ok(2));
+        status.record("B", EntryStatus::Empty);
+        assert_eq!(status.progress().title, "A daily worker is running (2/3)");
+
+        status.record("C", EntryStatus::Failed("timed out".to_string()));
+        let stats = RunStats {
+            entries: 3,
+            processed: 3,
+            new_books: 2,
+            empty: 1,
+            failed: 1,
+            duration: Duration::from_secs(65),
+        };
+        let summary = status.summary(&stats, 0, None);
+        assert!(summary
+            .description
+            .contains("Processed: **3** | New: **2** | Failed: **1** | Duration: 1m 05s"));
+        assert!(summary.description.contains("❌ C: timed out"));
+        assert_eq!(summary.color, Some(FAILED_COLOR));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(5)), "5s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 05s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h 02m 03s");
+    }
+}
@@ -1,9 +1,11 @@
-use std::env;
+use crate::{config::notify::resolve_webhook, context::AppContext};
 
-pub fn daily_url() -> Result<String, env::VarError> {
-    env::var("DAILY")
+/// Webhook of the daily status message, `$DAILY` by default
+pub fn daily_url(ctx: &AppContext) -> Result<String, String> {
+    resolve_webhook(&ctx.notify.daily.webhook)
 }
 
-pub fn processed_url() -> Result<String, env::VarError> {
-    env::var("PROCESSED")
+/// Webhook for entries no route matches, `$PROCESSED` by default
+pub fn processed_url(ctx: &AppContext) -> Result<String, String> {
+    resolve_webhook(&ctx.notify.processed.webhook)
 }
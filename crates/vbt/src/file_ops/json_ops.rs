@@ -1,17 +1,27 @@
-use crate::utils::fs::ensure_parent_dir;
-use std::{
-    fs::File,
-    io::{Read, Write},
-};
-
-pub fn save_json(data: &str, path: &str) -> Result<(), String> {
-    ensure_parent_dir(path)?;
-    File::create(path)
-        .map_err(|e| format!("Failed to create {}: {}", path, e))?
-        .write_all(data.as_bytes())
-        .map_err(|e| format!("Failed to write to {}: {}", path, e))?;
-
-    Ok(())
+use crate::utils::fs::write_atomic;
+use std::{fs::File, io::Read};
+
+/// Write `data` to `path` unless the file already holds the same JSON value.
+/// Returns whether the file was written
+pub fn save_json(data: &str, path: &str) -> Result<bool, String> {
+    if !json_differs(data, path) {
+        return Ok(false);
+    }
+
+    write_atomic(path, data.as_bytes())?;
+    Ok(true)
+}
+
+/// Whether [`save_json`] would write `data` to `path`
+pub fn json_differs(data: &str, path: &str) -> bool {
+    if let Ok(existing) = read_json(path) {
+        let existing = serde_json::from_str::<serde_json::Value>(&existing);
+        let new = serde_json::from_str::<serde_json::Value>(data);
+        if let (Ok(existing), Ok(new)) = (existing, new) {
+            return existing != new;
+        }
+    }
+    true
 }
 
 pub fn read_json(path: &str) -> Result<String, String> {
@@ -23,3 +33,25 @@ pub fn read_json(path: &str) -> Result<String, String> {
 
     Ok(contents)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::TempDir;
+
+    #[test]
+    fn test_save_json() {
+        let dir = TempDir::new("json");
+        let path = dir.file("Entry.json");
+
+        assert!(save_json(r#"{"name": "Entry", "books": [1, 2]}"#, &path).unwrap());
+        // The same value laid out differently is left alone
+        assert!(!json_differs("{\"name\":\"Entry\",\"books\":[1,2]}", &path));
+        assert!(!save_json("{\"name\":\"Entry\",\"books\":[1,2]}", &path).unwrap());
+        assert_eq!(
+            read_json(&path).unwrap(),
+            r#"{"name": "Entry", "books": [1, 2]}"#
+        );
+        assert!(save_json(r#"{"name": "Entry", "books": [1]}"#, &path).unwrap());
+    }
+}
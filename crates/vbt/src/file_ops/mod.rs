@@ -1,3 +1,4 @@
 //! File operations
 pub mod json_ops;
+pub mod opml_ops;
 pub mod rss_ops;
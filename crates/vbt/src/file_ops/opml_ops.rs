@@ -0,0 +1,53 @@
+use crate::{context::AppContext, types::watchlist::WatchlistEntry};
+use rss::opml::{Opml, Outline};
+use std::{collections::HashSet, fs, path::Path};
+
+/// Generate an OPML document listing every feed in `paths.rss_dir`.
+/// Feeds belonging to a watchlist entry use its name and alternate titles,
+/// feeds left over from removed entries fall back to their file name
+pub fn generate_and_save_opml(
+    ctx: &AppContext,
+    watchlist: &[WatchlistEntry],
+) -> Result<(), String> {
+    let config = &ctx.config;
+    let rss_dir = ctx.path(&config.paths.rss_dir);
+    let rss_dir = Path::new(&rss_dir);
+    let base_url = &config.urls.feed_base;
+    let mut outlines = Vec::new();
+    let mut listed = HashSet::new();
+
+    for entry in watchlist {
+        let slug = entry.slug();
+        if !rss_dir.join(format!("{}.rss", slug)).exists() || !listed.insert(slug.clone()) {
+            continue;
+        }
+
+        outlines.push(
+            Outline::new(&entry.name, format!("{}/{}.rss", base_url, slug))
+                .html_url(&config.urls.link)
+                .description(format!("Alternative Titles: {}", entry.alt_titles())),
+        );
+    }
+
+    let mut leftovers: Vec<String> = fs::read_dir(rss_dir)
+        .map_err(|e| format!("Failed to read {}: {}", rss_dir.display(), e))?
+        .filter_map(|dir_entry| dir_entry.ok())
+        .map(|dir_entry| dir_entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rss"))
+        .filter_map(|path| path.file_stem()?.to_str().map(str::to_string))
+        .filter(|slug| !listed.contains(slug))
+        .collect();
+    leftovers.sort();
+
+    for slug in leftovers {
+        outlines.push(
+            Outline::new(slug.replace("_", " "), format!("{}/{}.rss", base_url, slug))
+                .html_url(&config.urls.link),
+        );
+    }
+
+    Opml::new("VBT feeds")
+        .outlines(outlines)
+        .save_to_file(ctx.path(&config.paths.opml))
+        .map_err(|e| format!("Failed to save OPML: {}", e))
+}
@@ -1,69 +1,161 @@
 use crate::{
+    context::AppContext,
+    services::{cover_resolver::CoverResolver, covers::Covers},
     types::{book::BookRow, watchlist::WatchlistEntry},
     utils::{
-        cache::{generate_cache_key, load_cache, save_cache},
-        url::get_mime_type,
+        cache::{generate_cache_key, load_cache, save_cache, Cache},
+        url::registry_search_url,
     },
 };
 use chrono::{DateTime, Utc};
-use rss::write::{Channel, Item};
-use std::collections::HashSet;
+use rss::{
+    read,
+    write::{Channel, Item},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Prefix of the `dc:identifier` that carries the ISBN of an item
+const ISBN_URN: &str = "urn:isbn:";
+
+/// Recover the cache key of an item written by [`generate_and_save_rss`]
+/// from its `dc:identifier`. Feeds written before the identifier only had
+/// the ISBN in the description, it is read from there for them
+fn cache_key_from_item(item: &read::Item) -> Option<String> {
+    let isbn = match &item.dc_identifier {
+        Some(identifier) => identifier.strip_prefix(ISBN_URN)?,
+        None => {
+            let (_, rest) = item.description.split_once("| ISBN: ")?;
+            rest.split(" | ").next()?
+        }
+    };
+    Some(format!("{}-{}", isbn, item.title))
+}
+
+/// Fill in pub dates missing from the cache using an already published feed,
+/// so losing the date cache doesn't republish every book as new
+fn restore_cache_from_feed(cache: &mut Cache<String>, published: &read::Channel, name: &str) {
+    for item in &published.items {
+        if let (Some(key), Some(pub_date)) = (cache_key_from_item(item), item.pub_date) {
+            cache.data.entry(key).or_insert(pub_date.to_rfc2822());
+        }
+    }
+    if let Some(last_build_date) = published.last_build_date {
+        cache
+            .title_build_date
+            .entry(name.to_string())
+            .or_insert(last_build_date.to_rfc2822());
+    }
+}
+
+/// What changed in a feed compared to the published version, by cache key
+#[derive(Debug, Default)]
+pub struct FeedUpdate {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    /// Whether the feed file was rewritten, or would be on a dry run
+    pub written: bool,
+}
+
+impl FeedUpdate {
+    pub fn has_changes(&self) -> bool {
+        !self.added.is_empty() || !self.changed.is_empty() || !self.removed.is_empty()
+    }
+}
+
+fn item_key(item: &read::Item) -> String {
+    cache_key_from_item(item).unwrap_or_else(|| item.guid.clone())
+}
+
+/// Items are compared as a whole, so a new partner, translator or cover
+/// counts as a change too
+fn diff_items(published: &[read::Item], generated: &[read::Item]) -> FeedUpdate {
+    let published: HashMap<_, _> = published.iter().map(|i| (item_key(i), i)).collect();
+    let generated_keys: HashSet<_> = generated.iter().map(item_key).collect();
+    let mut update = FeedUpdate::default();
+
+    for item in generated {
+        let key = item_key(item);
+        match published.get(&key) {
+            None => update.added.push(key),
+            // Gaining the ISBN identifier alone isn't news
+            Some(old) if old.dc_identifier.is_none() && identified(old, item) == *item => {}
+            Some(old) if *old != item => update.changed.push(key),
+            Some(_) => {}
+        }
+    }
+    update.removed = published
+        .keys()
+        .filter(|key| !generated_keys.contains(*key))
+        .cloned()
+        .collect();
 
+    update
+}
+
+/// `old` with the `dc:identifier` of `new`
+fn identified(old: &read::Item, new: &read::Item) -> read::Item {
+    read::Item {
+        dc_identifier: new.dc_identifier.clone(),
+        ..old.clone()
+    }
+}
+
+/// Generate the feed for `watchlist_entry` and write it to its file in
+/// `paths.rss_dir` if it differs from what is already there. The channel
+/// dates only move when items were added, changed or removed. A dry run
+/// only reports what would change and writes nothing, the date cache
+/// included. Each item links the cover of its own volume, see
+/// [`CoverResolver`]
 pub fn generate_and_save_rss(
+    ctx: &AppContext,
     books: &[BookRow],
     watchlist_entry: &WatchlistEntry,
-    output_path: impl AsRef<std::path::Path>,
-) -> Result<(), String> {
+    dry_run: bool,
+) -> Result<FeedUpdate, String> {
+    let config = &ctx.config;
+    let output_path = ctx.path(&config.paths.rss_file(&watchlist_entry.slug()));
+    let cache_path = ctx.path(&config.paths.date_cache);
+    let link = &config.urls.link;
     let now = Utc::now();
-    let mut cache = load_cache::<String>("date_cache.json");
+    let mut cache = load_cache::<String>(&cache_path)?;
+    let original_cache = cache.clone();
 
-    let new_titles: HashSet<_> = books
-        .iter()
-        .map(|book| generate_cache_key(book))
-        .filter(|key| !cache.data.contains_key(key))
-        .collect();
-
-    let title_build_date: DateTime<Utc> = if !new_titles.is_empty() {
-        cache
-            .title_build_date
-            .insert(watchlist_entry.name.clone(), now.to_rfc2822());
-        now
-    } else {
-        cache
-            .title_build_date
-            .get(&watchlist_entry.name)
-            .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
-            .map(|d| d.with_timezone(&Utc))
-            .unwrap_or(now)
-    };
+    let published = read::Channel::read_from_file(&output_path).ok();
+    if let Some(published) = &published {
+        restore_cache_from_feed(&mut cache, published, &watchlist_entry.name);
+    }
 
-    let alt_titles = {
-        let mut titles: Vec<_> = watchlist_entry
-            .other
-            .iter()
-            .map(|(_, value)| value.to_string())
-            .collect();
-        titles.sort();
-        titles.join(", ")
-    };
+    let alt_titles = watchlist_entry.alt_titles();
+    let covers = Covers::load(ctx)?;
+    let resolver = CoverResolver::load(ctx)?;
 
-    let mut channel = Channel::new(
+    let channel = Channel::new(
         &watchlist_entry.name,
-        "https://github.com/Irilith/VBT",
+        link,
         format!("VBT feed for: {}", watchlist_entry.name),
     )
     .image(
-        &watchlist_entry.cover,
+        covers.thumbnail_url(&watchlist_entry.cover),
         &watchlist_entry.name,
-        "https://github.com/Irilith/VBT",
+        link,
     )
-    .pub_date(now)
-    .last_build_date(title_build_date);
+    .language("vi")
+    .generator("VBT")
+    .ttl(24 * 60)
+    .atom_link(format!(
+        "{}/{}.rss",
+        config.urls.feed_base,
+        watchlist_entry.slug()
+    ));
+    let mut channel = watchlist_entry
+        .genres
+        .iter()
+        .fold(channel, |channel, genre| channel.category(genre));
 
     for book in books {
         let cache_key = generate_cache_key(book);
-        let mime_type = get_mime_type(&watchlist_entry.cover);
-        // let pub_date = cache.data.entry(cache_key).or_insert(now).clone();
+        let cover = covers.link(resolver.resolve(&book.isbn, &watchlist_entry.cover));
 
         let pub_date_str = cache
             .data
@@ -71,32 +163,201 @@ pub fn generate_and_save_rss(
             .or_insert(now.to_rfc2822())
             .clone();
         let pub_date = DateTime::parse_from_rfc2822(&pub_date_str)
-            .unwrap()
+            .map_err(|e| format!("Invalid date {} in date cache: {}", pub_date_str, e))?
             .with_timezone(&Utc);
 
-        let item = Item::new(
+        let mut item = Item::new(
             &book.title,
-            "https://github.com/Irilith/VBT",
+            link,
             format!(
                 "Author: {} | Translator: {} | ISBN: {} | Alternative Titles: {}",
                 book.author, book.translator, book.isbn, alt_titles
             ),
-            format!(
-                "https://ppdvn.gov.vn/web/guest/ke-hoach-xuat-ban?query={}",
-                book.title.trim_end().replace(" ", "+")
-            ),
+            registry_search_url(&config.urls.registry, &book.title),
         )
-        .enclosure(&watchlist_entry.cover, 0, mime_type)
+        .dc_identifier(format!("{}{}", ISBN_URN, book.isbn))
+        .enclosure(&cover.url, cover.length, &cover.mime_type)
         .pub_date(pub_date);
 
+        if !book.translator.is_empty() {
+            item = item.dc_creator(&book.translator);
+        }
+        if !book.partner.is_empty() {
+            item = item.category(&book.partner);
+        }
+
         channel = channel.item(item);
     }
 
-    if let Err(e) = save_cache(&cache, "date_cache.json") {
-        eprintln!("Failed to save date cache: {}", e);
+    let generated = parse_built(&channel)?;
+    let mut update = diff_items(
+        published.as_ref().map_or(&[][..], |p| &p.items),
+        &generated.items,
+    );
+
+    let (pub_date, last_build_date) = if update.has_changes() {
+        cache
+            .title_build_date
+            .insert(watchlist_entry.name.clone(), now.to_rfc2822());
+        (now, now)
+    } else {
+        let last_build_date = cache
+            .title_build_date
+            .get(&watchlist_entry.name)
+            .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
+            .map(|d| d.with_timezone(&Utc))
+            .unwrap_or(now);
+        let pub_date = published.as_ref().and_then(|p| p.pub_date).unwrap_or(now);
+        (pub_date, last_build_date)
+    };
+    channel = channel.pub_date(pub_date).last_build_date(last_build_date);
+
+    // Metadata such as the cover can change without touching the items,
+    // the file is still rewritten then but with the old dates
+    if published.as_ref() != Some(&parse_built(&channel)?) {
+        update.written = true;
+        if !dry_run {
+            // Write the feed before the cache. A crash in between loses the
+            // new dates from the cache, but the next run takes them back from
+            // the feed with restore_cache_from_feed, so nothing is republished
+            channel
+                .save_to_file(&output_path)
+                .map_err(|e| format!("Failed to save RSS: {}", e))?;
+        }
+    }
+
+    if !dry_run && cache != original_cache {
+        save_cache(&cache, &cache_path)?;
+    }
+
+    Ok(update)
+}
+
+/// Read back a channel the way it would be written to disk
+fn parse_built(channel: &Channel) -> Result<read::Channel, String> {
+    let xml = channel
+        .build()
+        .map_err(|e| format!("Failed to build RSS: {}", e))?;
+    read::Channel::parse(&xml).map_err(|e| format!("Failed to read back RSS: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::app::AppConfig, context::TempDir};
+    use std::fs;
+
+    fn context(name: &str) -> (AppContext, TempDir) {
+        AppContext::temp(&format!("rss_{}", name), AppConfig::default())
+    }
+
+    #[test]
+    fn test_contexts_are_isolated() {
+        let (first, first_root) = context("first");
+        let (second, second_root) = context("second");
+        let entry = WatchlistEntry {
+            name: "Entry".to_string(),
+            cover: "https://example.com/cover.jpg".to_string(),
+            other: HashMap::new(),
+            tags: Vec::new(),
+            ..Default::default()
+        };
+        let book = BookRow::sample("978-604", "Entry 1");
+
+        let update = generate_and_save_rss(&first, &[book], &entry, false).unwrap();
+        assert_eq!(update.added, ["978-604-Entry 1"]);
+        assert!(update.written);
+        assert!(first_root.path().join("feed/rss/Entry.rss").exists());
+        assert!(first_root.path().join("date_cache.json").exists());
+
+        let update = generate_and_save_rss(&second, &[], &entry, true).unwrap();
+        assert!(!update.has_changes());
+        assert!(!second_root.path().exists());
     }
 
-    channel
-        .save_to_file(output_path)
-        .map_err(|e| format!("Failed to save RSS: {}", e))
+    #[test]
+    fn test_isbn_identifier() {
+        let item = read::Item {
+            title: "Entry 1".to_string(),
+            description: "Whatever the description says".to_string(),
+            dc_identifier: Some("urn:isbn:978-604".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cache_key_from_item(&item).unwrap(), "978-604-Entry 1");
+
+        // A feed from before the identifier gains it without any change
+        let (ctx, root) = context("identifier");
+        let entry = WatchlistEntry {
+            name: "Entry".to_string(),
+            ..Default::default()
+        };
+        let book = BookRow::sample("978-604", "Entry 1");
+        let path = root.path().join("feed/rss/Entry.rss");
+        generate_and_save_rss(&ctx, std::slice::from_ref(&book), &entry, false).unwrap();
+        let legacy: String = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.contains("dc:identifier"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        fs::write(&path, legacy).unwrap();
+        fs::remove_file(root.path().join("date_cache.json")).unwrap();
+
+        let update = generate_and_save_rss(&ctx, &[book], &entry, false).unwrap();
+        assert!(!update.has_changes());
+        assert!(update.written);
+        let published = read::Channel::read_from_file(&path).unwrap();
+        assert_eq!(
+            published.items[0].dc_identifier.as_deref(),
+            Some("urn:isbn:978-604")
+        );
+    }
+
+    #[test]
+    fn test_unchanged_rows_write_nothing() {
+        let (ctx, root) = context("unchanged");
+        let entry = WatchlistEntry {
+            name: "Entry".to_string(),
+            ..Default::default()
+        };
+        let book = |translator: &str| BookRow {
+            translator: translator.to_string(),
+            ..BookRow::sample("978-604", "Entry 1")
+        };
+        let path = root.path().join("feed/rss/Entry.rss");
+
+        generate_and_save_rss(&ctx, &[book("A")], &entry, false).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let built = read::Channel::read_from_file(&path)
+            .unwrap()
+            .last_build_date;
+        assert!(built.is_some());
+
+        let update = generate_and_save_rss(&ctx, &[book("A")], &entry, false).unwrap();
+        assert!(!update.written && !update.has_changes());
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+        let published = read::Channel::read_from_file(&path).unwrap();
+        assert_eq!(published.last_build_date, built);
+
+        let update = generate_and_save_rss(&ctx, &[book("B")], &entry, false).unwrap();
+        assert!(update.added.is_empty() && update.removed.is_empty());
+        assert_eq!(update.changed, ["978-604-Entry 1"]);
+        assert!(update.written);
+
+        // A field outside the description, here the partner category
+        let partnered = BookRow {
+            partner: "IPM".to_string(),
+            ..book("B")
+        };
+        let update = generate_and_save_rss(&ctx, &[partnered], &entry, false).unwrap();
+        assert_eq!(update.changed, ["978-604-Entry 1"]);
+        let published = read::Channel::read_from_file(&path).unwrap();
+        assert_eq!(published.items[0].categories, ["IPM"]);
+
+        let update = generate_and_save_rss(&ctx, &[], &entry, false).unwrap();
+        assert!(update.added.is_empty() && update.changed.is_empty());
+        assert_eq!(update.removed, ["978-604-Entry 1"]);
+    }
 }
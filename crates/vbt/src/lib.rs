@@ -1,8 +1,14 @@
+pub mod cli;
+pub mod commands;
 pub mod config;
+pub mod context;
 pub mod discord;
 pub mod file_ops;
 pub mod json;
+pub mod notifier;
 pub mod requests;
+pub mod server;
 pub mod services;
+pub mod telegram;
 pub mod types;
 pub mod utils;
@@ -1,156 +1,58 @@
-use chrono::{Duration, Utc};
+use clap::Parser;
 use dotenv::dotenv;
+use std::process;
 use vbt_lib::{
-    config::flag::{EnvFlag, FT_CONFIG},
-    discord::{
-        send::{send, DiscordEmbed, EmbedFooter, EmbedThumbnail},
-        wh,
-    },
-    file_ops::{json_ops, rss_ops},
-    json::return_json::return_json,
-    requests::get_data,
-    services::watchlist::load_watchlist,
-    utils::{
-        cache::sanitize_filename,
-        time::{generate_time, generate_unix_timestamp},
-    },
+    cli::{Cli, Command},
+    commands::{cache, daemon, discover, notify_test, rebuild, run, search, serve, watchlist},
+    config::{app::AppConfig, notify::NotifyConfig},
+    context::AppContext,
 };
 
-async fn send_webhook_message(
-    url: String,
-    mention: String,
-    embed: DiscordEmbed,
-    config: &EnvFlag,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if config.ft_webhook {
-        send(url, mention, Some(embed))
-            .await
-            .map_err(Box::<dyn std::error::Error>::from)?;
-    }
-    Ok(())
-}
-
-async fn process_watchlist(id: i64, config: &EnvFlag) -> Result<(), Box<dyn std::error::Error>> {
-    let watchlist = load_watchlist()?;
-
-    for entry in watchlist {
-        // Fetch data using name in the watchlist
-        let rows = match get_data::extract_table_data(&entry.name).await {
-            Ok(data) => data,
-            Err(e) => {
-                eprintln!("Failed to fetch data for {}: {}", entry.name, e);
-                continue;
-            }
-        };
-        if rows.is_empty() {
-            eprintln!("Entry {} is empty, skipping", entry.name);
-        } else {
-            // Generate JSON for json file
-            let json_data = return_json(&entry.name, &rows).map_err(|e| {
-                Box::<dyn std::error::Error>::from(format!(
-                    "Failed to generate JSON for {}: {}",
-                    entry.name, e
-                ))
-            })?;
-
-            // Get romaji filename or use "name" if not available
-            let filename_base = entry
-                .other
-                .get("romaji")
-                .unwrap_or(&entry.name)
-                .replace(" ", "_");
-
-            json_ops::save_json(
-                &json_data,
-                &format!("feed/json/{}.json", sanitize_filename(&filename_base)),
-            )?;
-
-            rss_ops::generate_and_save_rss(
-                &rows,
-                &entry,
-                format!("feed/rss/{}.rss", sanitize_filename(filename_base)),
-            )?;
-            println!("Processed: {}", entry.name);
-
-            if config.ft_webhook {
-                let url = wh::processed_url().map_err(Box::<dyn std::error::Error>::from)?;
-                let title = entry.name;
-                let description = format!("Id: **{}**", id);
-                let embed = DiscordEmbed {
-                    title,
-                    description,
-                    footer: Some(EmbedFooter {
-                        text: format!("VBT - {}", generate_time()),
-                        ..Default::default()
-                    }),
-                    thumbnail: Some(EmbedThumbnail { url: entry.cover }),
-                    ..Default::default()
-                };
-
-                send_webhook_message(url, "<@&1304123731442012220>".to_string(), embed, config)
-                    .await?;
-            }
-        }
-    }
-    Ok(())
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load .env file
     dotenv().ok();
 
-    let future_start_time = (Utc::now() + Duration::hours(24)).timestamp();
-    let config = FT_CONFIG.read().unwrap();
-
-    let id = generate_unix_timestamp();
-
-    if config.ft_webhook {
-        let url = wh::daily_url().map_err(Box::<dyn std::error::Error>::from)?;
-        let title = "A daily worker has started".to_string();
-        let description = format!("Id: {}", id);
-        let embed = DiscordEmbed {
-            title,
-            description,
-            footer: Some(EmbedFooter {
-                text: format!("VBT - {}", generate_time()),
-                ..Default::default()
-            }),
-            ..Default::default()
-        };
-
-        send_webhook_message(
-            url.clone(),
-            "<@&1304134014734434315>".to_string(),
-            embed,
-            &config,
-        )
-        .await?;
+    let cli = Cli::parse();
+    let opts = &cli.global;
+
+    // Fail before doing anything on a bad config
+    let loaded = AppConfig::load(opts.config.as_deref(), &opts.overrides).and_then(|config| {
+        let notify = NotifyConfig::load(&config.notify.file)?;
+        Ok((config, notify))
+    });
+    let (mut config, notify) = loaded.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2)
+    });
+
+    let command = cli.command.unwrap_or(Command::Run { only: None });
+    // The test has to reach the backends even when notifications are off
+    if matches!(command, Command::NotifyTest) {
+        config.notify.enabled = true;
+    }
+    let ctx = AppContext::new(config, notify);
+    // Only these commands notify, and a dry run never does
+    let notifies = matches!(
+        command,
+        Command::Run { .. } | Command::NotifyTest | Command::Serve | Command::Daemon
+    );
+    let ctx = if notifies && !opts.dry_run {
+        ctx.with_notifiers()?
+    } else {
+        ctx
+    };
+
+    match command {
+        Command::Run { only } => run::run(&ctx, opts, only.as_deref()).await,
+        Command::Search { query, json } => search::search(&ctx, &query, json).await,
+        Command::Discover { title, sample } => discover::discover(&ctx, opts, &title, sample).await,
+        Command::Add(args) => watchlist::add(&ctx, opts, args),
+        Command::Remove { name } => watchlist::remove(&ctx, opts, &name),
+        Command::RebuildFeeds => rebuild::rebuild_feeds(&ctx, opts).await,
+        Command::Cache { action } => cache::cache(&ctx, opts, &action),
+        Command::NotifyTest => notify_test::notify_test(&ctx, opts).await,
+        Command::Serve => serve::serve(&ctx, opts).await,
+        Command::Daemon => daemon::daemon(&ctx, opts).await,
     }
-
-    match process_watchlist(id, &config).await {
-        Ok(_) => {
-            if config.ft_webhook {
-                let url = wh::daily_url().map_err(Box::<dyn std::error::Error>::from)?;
-                let title = "Finished".to_string();
-                // <t:TIME:R> Relative
-                let description = format!("Next automatic worker is: <t:{}:R>", future_start_time);
-                let embed = DiscordEmbed {
-                    title,
-                    description,
-                    footer: Some(EmbedFooter {
-                        text: format!("VBT - {}", generate_time()),
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                };
-
-                send_webhook_message(url, "".to_string(), embed, &config).await?;
-            }
-            Ok(())
-        }
-        Err(e) => Err(e),
-    }?;
-
-    Ok(())
 }
@@ -0,0 +1,375 @@
+use super::{first_cover, or_dash, Event, Notifier};
+use crate::{
+    config::notify::{NotifyConfig, Target},
+    context::AppContext,
+    discord::{
+        queue::DeliveryQueue,
+        send::{
+            AllowedMentions, DiscordEmbed, EmbedField, EmbedFooter, EmbedThumbnail, WebhookPayload,
+            DEFAULT_COLOR, MAX_FIELDS,
+        },
+        status::{EntryStatus, StatusMessage},
+    },
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::{time::generate_time, url::registry_search_url},
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use chrono_tz::Tz;
+
+/// Colour of the scraper broken alert
+const ALERT_COLOR: u32 = 0xff0000;
+
+/// Discord webhooks: one status message on the `daily` webhook that is
+/// edited as the run goes, and a message per entry with new books
+pub struct DiscordNotifier {
+    notify: NotifyConfig,
+    queue: DeliveryQueue,
+    status: Option<StatusMessage>,
+    registry: String,
+    tz: Tz,
+    /// A `vbt notify-test` run, see [`Event::RunStarted`]
+    test: bool,
+    /// Messages dead-lettered while handling the current event
+    undelivered: usize,
+}
+
+impl DiscordNotifier {
+    pub fn new(ctx: &AppContext) -> Self {
+        let config = &ctx.config;
+        DiscordNotifier {
+            notify: ctx.notify.clone(),
+            queue: DeliveryQueue::new(ctx.path(&config.notify.dead_letter))
+                .client(ctx.client.clone())
+                .webhooks(ctx.notify.discord_webhooks()),
+            status: None,
+            registry: config.urls.registry.clone(),
+            tz: config.time.tz(),
+            test: false,
+            undelivered: 0,
+        }
+    }
+
+    async fn send(&mut self, target: &Target, mention: bool, embed: DiscordEmbed) {
+        let content = if mention {
+            target.mention()
+        } else {
+            String::new()
+        };
+        let payload = WebhookPayload {
+            // Only ping the configured roles, never whatever ends up in a title
+            allowed_mentions: Some(AllowedMentions::roles(&target.roles)),
+            ..WebhookPayload::new(content)
+        }
+        .embed(DiscordEmbed {
+            color: Some(embed.color.unwrap_or(DEFAULT_COLOR)),
+            timestamp: Some(Utc::now().to_rfc3339()),
+            ..embed
+        });
+        // Failed messages are dead-lettered and retried on the next run
+        if self.queue.deliver(&target.webhook, payload).await.is_none() {
+            self.undelivered += 1;
+        }
+    }
+
+    /// Post the status message, keeping its id so it can be edited later
+    async fn post_status(&mut self, embed: DiscordEmbed) {
+        let target = self.notify.daily.clone();
+        let payload = WebhookPayload {
+            allowed_mentions: Some(AllowedMentions::roles(&target.roles)),
+            ..WebhookPayload::new(target.mention())
+        }
+        .embed(embed);
+
+        let ids = self.queue.deliver(&target.webhook, payload).await;
+        if ids.is_none() {
+            self.undelivered += 1;
+        }
+        let message_id = ids.and_then(|ids| ids.into_iter().next());
+        if let Some(status) = &mut self.status {
+            status.message_id = message_id;
+        }
+    }
+
+    /// Edit the status message in place. Returns false when there is no
+    /// message to edit because the start message never made it
+    async fn update_status(&mut self, embed: DiscordEmbed) -> bool {
+        let Some(message_id) = self.status.as_ref().and_then(|s| s.message_id.clone()) else {
+            return false;
+        };
+
+        let payload = WebhookPayload::default().embed(embed);
+        if let Err(e) = self
+            .queue
+            .edit(&self.notify.daily.webhook, &message_id, payload)
+            .await
+        {
+            eprintln!("Failed to update the status message: {}", e);
+        }
+        true
+    }
+
+    async fn record(&mut self, entry: &WatchlistEntry, entry_status: EntryStatus) {
+        let Some(status) = &mut self.status else {
+            return;
+        };
+        status.record(&entry.name, entry_status);
+        let embed = status.progress();
+        self.update_status(embed).await;
+    }
+
+    /// Embed listing the new or changed books of an entry, one field per
+    /// book up to Discord's 25, with the cover of the first one that has
+    /// its own. `covers` are in the same order as `books`
+    fn new_books_embed(
+        &self,
+        id: i64,
+        entry: &WatchlistEntry,
+        books: &[&BookRow],
+        covers: &[&str],
+    ) -> DiscordEmbed {
+        let fields = books
+            .iter()
+            .take(MAX_FIELDS)
+            .map(|book| EmbedField {
+                name: book.title.clone(),
+                value: format!(
+                    "ISBN: {}\nTranslator: {}\nPrint run: {}\nPartner: {}",
+                    or_dash(&book.isbn),
+                    or_dash(&book.translator),
+                    or_dash(&book.quantity),
+                    or_dash(&book.partner)
+                ),
+                inline: false,
+            })
+            .collect();
+
+        let mut description = format!("{} new or updated book(s) | Id: **{}**", books.len(), id);
+        if books.len() > MAX_FIELDS {
+            description.push_str(&format!(
+                "\n{} more not shown, see the feed",
+                books.len() - MAX_FIELDS
+            ));
+        }
+
+        let cover = first_cover(entry, covers);
+        DiscordEmbed {
+            title: entry.name.clone(),
+            description,
+            url: Some(registry_search_url(&self.registry, &entry.name)),
+            fields: Some(fields),
+            footer: Some(EmbedFooter {
+                text: format!("VBT - {}", generate_time(self.tz)),
+                ..Default::default()
+            }),
+            thumbnail: (!cover.is_empty()).then(|| EmbedThumbnail {
+                url: cover.to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    async fn notify(&mut self, event: &Event<'_>) -> Result<(), String> {
+        self.undelivered = 0;
+        match *event {
+            Event::RunStarted { id, entries, test } => {
+                self.test = test;
+                // A test leaves the dead letters for the next real run
+                let delivered = if test {
+                    0
+                } else {
+                    self.queue.retry_dead_letters().await?
+                };
+                if delivered > 0 {
+                    println!(
+                        "Delivered {} message(s) left over from earlier runs",
+                        delivered
+                    );
+                }
+
+                let mut status = StatusMessage::new(id, self.tz);
+                status.set_total(entries);
+                let embed = status.progress();
+                self.status = Some(status);
+                self.post_status(embed).await;
+            }
+            Event::EntryProcessed {
+                entry,
+                rows,
+                new_books,
+                covers,
+            } => {
+                if !new_books.is_empty() {
+                    let id = self.status.as_ref().map_or(0, |s| s.id);
+                    let targets: Vec<Target> = self
+                        .notify
+                        .targets_for(entry)
+                        .into_iter()
+                        .cloned()
+                        .collect();
+                    let embed = self.new_books_embed(id, entry, new_books, covers);
+                    for target in targets {
+                        self.send(&target, true, embed.clone()).await;
+                    }
+                }
+
+                let entry_status = if rows == 0 {
+                    EntryStatus::Empty
+                } else {
+                    EntryStatus::Ok(new_books.len())
+                };
+                self.record(entry, entry_status).await;
+            }
+            Event::EntryFailed { entry, error } => {
+                self.record(entry, EntryStatus::Failed(error.to_string()))
+                    .await;
+            }
+            Event::RunFinished {
+                stats,
+                error,
+                next_run,
+            } => {
+                let Some(status) = &self.status else {
+                    return Ok(());
+                };
+                let summary = status.summary(stats, next_run, error);
+                if !self.update_status(summary.clone()).await {
+                    self.post_status(summary).await;
+                }
+            }
+            Event::ScraperBroken { error } => {
+                let embed = DiscordEmbed {
+                    title: "The scraper looks broken".to_string(),
+                    description: error.to_string(),
+                    color: Some(ALERT_COLOR),
+                    footer: Some(EmbedFooter {
+                        text: format!("VBT - {}", generate_time(self.tz)),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                };
+                let target = self.notify.daily.clone();
+                self.send(&target, true, embed).await;
+            }
+        }
+
+        // Outside a test, dead letters are retried by the next run instead
+        if self.test && self.undelivered > 0 {
+            return Err(format!("{} message(s) not delivered", self.undelivered));
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), String> {
+        if self.test {
+            return Ok(());
+        }
+        self.queue.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::app::AppConfig, context::Storage};
+    use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_new_books_embed() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "id": "1", "channel_id": "1" })),
+            )
+            .mount(&server)
+            .await;
+        let notify: NotifyConfig = serde_json::from_value(serde_json::json!({
+            "processed": { "webhook": format!("{}/processed", server.uri()) },
+            "routes": [
+                { "tags": ["manga"], "webhook": format!("{}/manga", server.uri()), "roles": ["9"] }
+            ]
+        }))
+        .unwrap();
+        let (storage, _root) = Storage::temp("discord");
+        let ctx = AppContext::new(AppConfig::default(), notify).storage(storage);
+        let mut notifier = DiscordNotifier::new(&ctx);
+
+        let entry = WatchlistEntry {
+            name: "Alpha".to_string(),
+            cover: "https://example.com/series.jpg".to_string(),
+            tags: vec!["manga".to_string()],
+            ..Default::default()
+        };
+        let book = |title: &str, isbn: &str| BookRow {
+            translator: "Someone".to_string(),
+            ..BookRow::sample(isbn, title)
+        };
+        let (first, second) = (book("Alpha 1", "978-1"), book("Alpha 2", ""));
+        notifier
+            .notify(&Event::EntryProcessed {
+                entry: &entry,
+                rows: 2,
+                new_books: &[&first, &second],
+                covers: &["https://example.com/1.jpg", ""],
+            })
+            .await
+            .unwrap();
+
+        // Only the matching route gets it, one field per book
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].url.path(), "/manga");
+        let payload: WebhookPayload = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(payload.content, "<@&9>");
+        assert_eq!(payload.embeds.len(), 1);
+        let embed = &payload.embeds[0];
+        assert_eq!(embed.title, "Alpha");
+        assert_eq!(embed.color, Some(DEFAULT_COLOR));
+        assert!(embed.description.starts_with("2 new or updated book(s)"));
+        let fields = embed.fields.as_ref().unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].name, "Alpha 1");
+        assert!(fields[0]
+            .value
+            .starts_with("ISBN: 978-1\nTranslator: Someone"));
+        assert!(fields[1].value.starts_with("ISBN: -"));
+        // The first book with a cover of its own
+        assert_eq!(
+            embed.thumbnail.as_ref().unwrap().url,
+            "https://example.com/1.jpg"
+        );
+
+        // Past 25 books the rest are counted, not listed
+        let many: Vec<BookRow> = (0..30).map(|i| book(&format!("Alpha {}", i), "")).collect();
+        let many: Vec<&BookRow> = many.iter().collect();
+        let embed = notifier.new_books_embed(7, &entry, &many, &[]);
+        assert_eq!(embed.fields.as_ref().unwrap().len(), MAX_FIELDS);
+        assert!(embed
+            .description
+            .ends_with("5 more not shown, see the feed"));
+        assert_eq!(
+            embed.thumbnail.as_ref().unwrap().url,
+            "https://example.com/series.jpg"
+        );
+
+        // Nothing new, nothing sent
+        notifier
+            .notify(&Event::EntryProcessed {
+                entry: &entry,
+                rows: 2,
+                new_books: &[],
+                covers: &[],
+            })
+            .await
+            .unwrap();
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,646 @@
+use super::{or_dash, Event, Notifier};
+use crate::{
+    config::notify::{EmailConfig, SmtpTls, Subscription},
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::{cache::generate_cache_key, fs::write_atomic, url::registry_search_url},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use lettre::{
+    message::MultiPart, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs::File, io::ErrorKind};
+
+/// Books collected since the last digest, kept in `EmailConfig::state`
+/// so a weekly digest survives daily runs
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+struct Digest {
+    /// When the last digest went out, the period starts here
+    since: Option<DateTime<Utc>>,
+    /// Keyed by entry name
+    entries: BTreeMap<String, DigestEntry>,
+    /// Entries a subscriber's last digest failed to reach, by address
+    undelivered: BTreeMap<String, BTreeMap<String, DigestEntry>>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+struct DigestEntry {
+    name: String,
+    cover: String,
+    tags: Vec<String>,
+    /// Keyed by cache key, a changed row replaces the earlier one
+    books: BTreeMap<String, DigestBook>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+struct DigestBook {
+    title: String,
+    isbn: String,
+    translator: String,
+    print_run: String,
+    partner: String,
+}
+
+impl Digest {
+    fn add(&mut self, entry: &WatchlistEntry, books: &[&BookRow]) {
+        let digest_entry = self
+            .entries
+            .entry(entry.name.clone())
+            .or_insert_with(|| DigestEntry {
+                name: entry.name.clone(),
+                ..Default::default()
+            });
+        digest_entry.cover = entry.cover.clone();
+        digest_entry.tags = entry.tags.clone();
+
+        for book in books {
+            digest_entry.books.insert(
+                generate_cache_key(book),
+                DigestBook {
+                    title: book.title.clone(),
+                    isbn: book.isbn.clone(),
+                    translator: book.translator.clone(),
+                    print_run: book.quantity.clone(),
+                    partner: book.partner.clone(),
+                },
+            );
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.undelivered.is_empty()
+    }
+
+    /// What `subscription` is sent: its undelivered entries merged with
+    /// the new ones, by entry name
+    fn pending_for(&self, subscription: &Subscription) -> BTreeMap<String, DigestEntry> {
+        let mut pending = self
+            .undelivered
+            .get(&subscription.address)
+            .cloned()
+            .unwrap_or_default();
+        for entry in self.entries_for(subscription) {
+            match pending.get_mut(&entry.name) {
+                Some(earlier) => {
+                    earlier.cover = entry.cover.clone();
+                    earlier.tags = entry.tags.clone();
+                    earlier.books.extend(entry.books.clone());
+                }
+                None => {
+                    pending.insert(entry.name.clone(), entry.clone());
+                }
+            }
+        }
+        pending
+    }
+
+    /// Entries `subscription` gets, in name order
+    fn entries_for<'a>(&'a self, subscription: &Subscription) -> Vec<&'a DigestEntry> {
+        self.entries
+            .values()
+            .filter(|entry| subscription.matches(&entry.name, &entry.tags))
+            .collect()
+    }
+}
+
+/// Mails a digest of the new books, grouped by entry, to every subscriber
+pub struct EmailNotifier {
+    config: EmailConfig,
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+    digest: Digest,
+    /// Whether `digest` differs from the state file
+    dirty: bool,
+    /// Books of a `vbt notify-test` run, mailed at its end without
+    /// touching `digest`
+    test: Option<Digest>,
+    registry: String,
+}
+
+impl EmailNotifier {
+    pub fn from_config(config: &EmailConfig, registry: &str) -> Result<Self, String> {
+        let smtp = &config.smtp;
+        let mut builder = match smtp.tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)
+                .map_err(|e| format!("SMTP relay {}: {}", smtp.host, e))?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.host),
+        }
+        .port(smtp.port);
+        if let Some((username, password)) = smtp.credentials()? {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        let mut notifier = EmailNotifier {
+            config: config.clone(),
+            mailer: builder.build(),
+            digest: load_digest(&config.state)?,
+            dirty: false,
+            test: None,
+            registry: registry.to_string(),
+        };
+        if notifier.digest.since.is_none() {
+            notifier.digest.since = Some(Utc::now());
+            notifier.dirty = true;
+        }
+        Ok(notifier)
+    }
+
+    fn is_due(&self) -> bool {
+        let since = self.digest.since.unwrap_or_else(Utc::now);
+        Utc::now() - since >= Duration::days(self.config.every_days.into())
+    }
+
+    /// Send the digest to every subscriber with at least one entry in it.
+    /// What a subscriber couldn't be sent is kept for them alone and goes
+    /// out with their next digest
+    async fn send_digest(&mut self) -> Result<(), String> {
+        let since = self.digest.since.unwrap_or_else(Utc::now);
+        let mut undelivered = BTreeMap::new();
+        let mut errors = Vec::new();
+
+        for subscription in &self.config.recipients {
+            let entries = self.digest.pending_for(subscription);
+            if entries.is_empty() {
+                continue;
+            }
+            let entries_ref: Vec<_> = entries.values().collect();
+            if let Err(e) = self.mail(&subscription.address, &entries_ref, since).await {
+                errors.push(e);
+                undelivered.insert(subscription.address.clone(), entries);
+            }
+        }
+
+        self.digest = Digest {
+            since: Some(Utc::now()),
+            entries: BTreeMap::new(),
+            undelivered,
+        };
+        self.dirty = true;
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Mail a `vbt notify-test` digest, nothing is kept on errors
+    async fn send_test(&self, digest: &Digest) -> Result<(), String> {
+        let since = digest.since.unwrap_or_else(Utc::now);
+        let mut errors = Vec::new();
+        for subscription in &self.config.recipients {
+            let entries = digest.entries_for(subscription);
+            if entries.is_empty() {
+                continue;
+            }
+            if let Err(e) = self.mail(&subscription.address, &entries, since).await {
+                errors.push(e);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Mail `entries` to `address`, the error is prefixed with the address
+    async fn mail(
+        &self,
+        address: &str,
+        entries: &[&DigestEntry],
+        since: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let message = Message::builder()
+            .from(
+                self.config
+                    .from
+                    .parse()
+                    .map_err(|e| format!("Invalid from address: {}", e))?,
+            )
+            .to(address.parse().map_err(|e| format!("{}: {}", address, e))?)
+            .subject(&self.config.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                render_text(&self.registry, entries, since),
+                render_html(&self.registry, entries, since),
+            ))
+            .map_err(|e| format!("Failed to build the digest: {}", e))?;
+
+        self.mailer
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("{}: {}", address, e))
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "Email"
+    }
+
+    async fn notify(&mut self, event: &Event<'_>) -> Result<(), String> {
+        match *event {
+            Event::RunStarted { test, .. } => {
+                if test {
+                    // Not even a first `since` is written by a test
+                    self.dirty = false;
+                }
+                self.test = test.then(|| Digest {
+                    since: Some(Utc::now()),
+                    ..Default::default()
+                });
+                Ok(())
+            }
+            Event::EntryProcessed {
+                entry, new_books, ..
+            } if !new_books.is_empty() => {
+                match &mut self.test {
+                    Some(test) => test.add(entry, new_books),
+                    None => {
+                        self.digest.add(entry, new_books);
+                        self.dirty = true;
+                    }
+                }
+                Ok(())
+            }
+            Event::RunFinished { .. } if self.test.is_some() => {
+                let test = self.test.take().unwrap_or_default();
+                self.send_test(&test).await
+            }
+            Event::RunFinished { .. } if !self.digest.is_empty() && self.is_due() => {
+                self.send_digest().await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn finish(&mut self) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let data = serde_json::to_vec_pretty(&self.digest)
+            .map_err(|e| format!("Failed to serialize the email digest: {}", e))?;
+        write_atomic(&self.config.state, &data)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn load_digest(path: &str) -> Result<Digest, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Digest::default()),
+        Err(e) => return Err(format!("Failed to open {}: {}", path, e)),
+    };
+    serde_json::from_reader(file).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+fn count_books(entries: &[&DigestEntry]) -> usize {
+    entries.iter().map(|entry| entry.books.len()).sum()
+}
+
+fn render_text(registry: &str, entries: &[&DigestEntry], since: DateTime<Utc>) -> String {
+    let mut text = format!(
+        "{} new or updated book(s) since {}\n",
+        count_books(entries),
+        since.format("%Y-%m-%d")
+    );
+    for entry in entries {
+        text.push_str(&format!(
+            "\n== {} ==\n{}\n",
+            entry.name,
+            registry_search_url(registry, &entry.name)
+        ));
+        for book in entry.books.values() {
+            text.push_str(&format!(
+                "\n- {}\n  ISBN: {} | Translator: {} | Print run: {} | Partner: {}\n  {}\n",
+                book.title,
+                or_dash(&book.isbn),
+                or_dash(&book.translator),
+                or_dash(&book.print_run),
+                or_dash(&book.partner),
+                registry_search_url(registry, &book.title)
+            ));
+        }
+    }
+    text
+}
+
+fn render_html(registry: &str, entries: &[&DigestEntry], since: DateTime<Utc>) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html><html><body style=\"font-family: sans-serif\">\
+         <p>{} new or updated book(s) since {}</p>",
+        count_books(entries),
+        since.format("%Y-%m-%d")
+    );
+    for entry in entries {
+        html.push_str("<table style=\"margin-top: 24px\"><tr>");
+        if !entry.cover.is_empty() {
+            html.push_str(&format!(
+                "<td style=\"vertical-align: top; padding-right: 16px\">\
+                 <img src=\"{}\" alt=\"\" width=\"96\"></td>",
+                escape(&entry.cover)
+            ));
+        }
+        html.push_str(&format!(
+            "<td style=\"vertical-align: top\"><h2 style=\"margin: 0\">\
+             <a href=\"{}\">{}</a></h2><ul>",
+            escape(&registry_search_url(registry, &entry.name)),
+            escape(&entry.name)
+        ));
+        for book in entry.books.values() {
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a><br>ISBN: {} | Translator: {} | \
+                 Print run: {} | Partner: {}</li>",
+                escape(&registry_search_url(registry, &book.title)),
+                escape(&book.title),
+                escape(or_dash(&book.isbn)),
+                escape(or_dash(&book.translator)),
+                escape(or_dash(&book.print_run)),
+                escape(or_dash(&book.partner))
+            ));
+        }
+        html.push_str("</ul></td></tr></table>");
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{app::AppConfig, notify::SmtpConfig},
+        context::TempDir,
+    };
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// Accepts every message but the ones to a `bounce@` address and keeps
+    /// the recipients and data of each
+    async fn smtp_sink() -> (u16, Arc<Mutex<Vec<(String, String)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let messages = received.clone();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let messages = messages.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    write.write_all(b"220 sink\r\n").await.unwrap();
+                    let (mut rcpt, mut data, mut in_data) = (String::new(), String::new(), false);
+
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let reply: &[u8] = if in_data {
+                            if line == "." {
+                                in_data = false;
+                                messages
+                                    .lock()
+                                    .unwrap()
+                                    .push((std::mem::take(&mut rcpt), std::mem::take(&mut data)));
+                                b"250 queued\r\n"
+                            } else {
+                                data.push_str(&line);
+                                data.push('\n');
+                                continue;
+                            }
+                        } else if line.starts_with("RCPT TO:<bounce@") {
+                            b"550 no such user\r\n"
+                        } else if line.starts_with("RCPT TO:") {
+                            rcpt.push_str(&line);
+                            b"250 ok\r\n"
+                        } else if line == "DATA" {
+                            in_data = true;
+                            b"354 go ahead\r\n"
+                        } else if line == "QUIT" {
+                            write.write_all(b"221 bye\r\n").await.unwrap();
+                            break;
+                        } else {
+                            b"250 ok\r\n"
+                        };
+                        write.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        (port, received)
+    }
+
+    fn entry(name: &str, tag: &str) -> WatchlistEntry {
+        WatchlistEntry {
+            name: name.to_string(),
+            cover: format!("https://example.com/{}.jpg", name),
+            other: HashMap::new(),
+            tags: vec![tag.to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn book(title: &str) -> BookRow {
+        BookRow::sample(&format!("isbn-{}", title), title)
+    }
+
+    fn subscription(address: &str, entries: &[&str], tags: &[&str]) -> Subscription {
+        Subscription {
+            address: address.to_string(),
+            entries: entries.iter().map(|s| s.to_string()).collect(),
+            tags: tags.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn config(port: u16, state: &str, recipients: Vec<Subscription>) -> EmailConfig {
+        EmailConfig {
+            smtp: SmtpConfig {
+                host: "127.0.0.1".to_string(),
+                port,
+                username: None,
+                password: None,
+                tls: SmtpTls::None,
+            },
+            from: "VBT <vbt@example.com>".to_string(),
+            subject: "Digest".to_string(),
+            every_days: 0,
+            state: state.to_string(),
+            recipients,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_digest_per_subscription() {
+        let (port, received) = smtp_sink().await;
+        let dir = TempDir::new("digest");
+        let state = dir.file("digest.json");
+        let config = config(
+            port,
+            &state,
+            vec![
+                subscription("manga@example.com", &[], &["manga"]),
+                subscription("alpha@example.com", &["Alpha"], &[]),
+                subscription("nobody@example.com", &["Gamma"], &[]),
+            ],
+        );
+        let mut notifier =
+            EmailNotifier::from_config(&config, &AppConfig::default().urls.registry).unwrap();
+
+        let (alpha, beta) = (entry("Alpha", "ln"), entry("Beta", "manga"));
+        let (alpha_1, beta_1) = (book("Alpha 1"), book("Beta 1"));
+        for (entry, book) in [(&alpha, &alpha_1), (&beta, &beta_1)] {
+            notifier
+                .notify(&Event::EntryProcessed {
+                    entry,
+                    rows: 1,
+                    new_books: &[book],
+                    covers: &[],
+                })
+                .await
+                .unwrap();
+        }
+        let stats = Default::default();
+        notifier
+            .notify(&Event::RunFinished {
+                stats: &stats,
+                error: None,
+                next_run: 0,
+            })
+            .await
+            .unwrap();
+        notifier.finish().await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let (rcpt, data) = &received[0];
+        assert!(rcpt.contains("manga@example.com"));
+        assert!(data.contains("Beta 1") && !data.contains("Alpha 1"));
+        assert!(data.contains("text/html") && data.contains("text/plain"));
+        let (rcpt, data) = &received[1];
+        assert!(rcpt.contains("alpha@example.com"));
+        assert!(data.contains("Alpha 1") && !data.contains("Beta 1"));
+
+        // Sent books are cleared from the state
+        let digest = load_digest(&state).unwrap();
+        assert!(digest.entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_test_run() {
+        let (port, received) = smtp_sink().await;
+        let dir = TempDir::new("digest_test");
+        let state = dir.file("digest.json");
+        let config = EmailConfig {
+            every_days: 7,
+            ..config(
+                port,
+                &state,
+                vec![subscription("all@example.com", &[], &[])],
+            )
+        };
+        let mut notifier =
+            EmailNotifier::from_config(&config, &AppConfig::default().urls.registry).unwrap();
+
+        let (alpha, alpha_1) = (entry("Alpha", "ln"), book("Alpha 1"));
+        let stats = Default::default();
+        for event in [
+            Event::RunStarted {
+                id: 1,
+                entries: 1,
+                test: true,
+            },
+            Event::EntryProcessed {
+                entry: &alpha,
+                rows: 1,
+                new_books: &[&alpha_1],
+                covers: &[],
+            },
+            Event::RunFinished {
+                stats: &stats,
+                error: None,
+                next_run: 0,
+            },
+        ] {
+            notifier.notify(&event).await.unwrap();
+        }
+        notifier.finish().await.unwrap();
+
+        // Mailed although the digest isn't due, and nothing saved
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].1.contains("Alpha 1"));
+        assert!(!std::path::Path::new(&state).exists());
+    }
+
+    #[tokio::test]
+    async fn test_failing_recipient() {
+        let (port, received) = smtp_sink().await;
+        let dir = TempDir::new("digest_bounce");
+        let state = dir.file("digest.json");
+        let config = config(
+            port,
+            &state,
+            vec![
+                subscription("bounce@example.com", &[], &[]),
+                subscription("ok@example.com", &[], &[]),
+            ],
+        );
+        let registry = AppConfig::default().urls.registry;
+        let stats = Default::default();
+        let finished = Event::RunFinished {
+            stats: &stats,
+            error: None,
+            next_run: 0,
+        };
+
+        let (alpha, beta) = (entry("Alpha", "ln"), entry("Beta", "manga"));
+        let (alpha_1, beta_1) = (book("Alpha 1"), book("Beta 1"));
+        for (entry, book) in [(&alpha, &alpha_1), (&beta, &beta_1)] {
+            let mut notifier = EmailNotifier::from_config(&config, &registry).unwrap();
+            notifier
+                .notify(&Event::EntryProcessed {
+                    entry,
+                    rows: 1,
+                    new_books: &[book],
+                    covers: &[],
+                })
+                .await
+                .unwrap();
+            let e = notifier.notify(&finished).await.unwrap_err();
+            assert!(e.starts_with("bounce@example.com: "));
+            notifier.finish().await.unwrap();
+        }
+
+        // The working address gets each book once
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(received[0].1.contains("Alpha 1"));
+        assert!(received[1].1.contains("Beta 1") && !received[1].1.contains("Alpha 1"));
+
+        // The failing one keeps everything for its next digest
+        let digest = load_digest(&state).unwrap();
+        assert!(digest.entries.is_empty());
+        let pending = &digest.undelivered["bounce@example.com"];
+        assert_eq!(pending.keys().collect::<Vec<_>>(), ["Alpha", "Beta"]);
+        assert!(!digest.undelivered.contains_key("ok@example.com"));
+    }
+}
@@ -0,0 +1,258 @@
+pub mod discord;
+pub mod email;
+pub mod ntfy;
+pub mod telegram;
+pub mod webhook;
+
+use crate::{
+    config::notify::{Backend, EmailConfig},
+    context::AppContext,
+    types::{book::BookRow, watchlist::WatchlistEntry},
+};
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// Totals of a run, sent with [`Event::RunFinished`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunStats {
+    /// Entries in the watchlist
+    pub entries: usize,
+    /// Entries that completed, failed ones included
+    pub processed: usize,
+    /// New or changed books over every entry
+    pub new_books: usize,
+    /// Entries the registry has no rows for
+    pub empty: usize,
+    pub failed: usize,
+    pub duration: Duration,
+}
+
+/// Something that happened while going through the watchlist
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// `test` is set by `vbt notify-test`: backends deliver right away,
+    /// report failed deliveries and leave their saved state alone
+    RunStarted { id: i64, entries: usize, test: bool },
+    /// The entry was fetched and its files written. `rows` is zero when the
+    /// registry has nothing for it, `new_books` are the added or changed rows
+    /// and `covers` the cover of each of them, in the same order
+    EntryProcessed {
+        entry: &'a WatchlistEntry,
+        rows: usize,
+        new_books: &'a [&'a BookRow],
+        covers: &'a [&'a str],
+    },
+    EntryFailed {
+        entry: &'a WatchlistEntry,
+        error: &'a str,
+    },
+    /// `error` is the reason the run stopped early, if it did.
+    /// `next_run` is the unix timestamp of the next scheduled run
+    RunFinished {
+        stats: &'a RunStats,
+        error: Option<&'a str>,
+        next_run: i64,
+    },
+    /// The registry didn't give us anything usable, the page probably changed
+    ScraperBroken { error: &'a str },
+}
+
+/// A place run events are sent to. Implementations pick the events they
+/// care about and ignore the rest
+#[async_trait]
+pub trait Notifier: Send {
+    /// Shown in logs when the notifier fails
+    fn name(&self) -> &'static str;
+
+    async fn notify(&mut self, event: &Event<'_>) -> Result<(), String>;
+
+    /// Called once after the run, e.g. to persist undelivered messages
+    async fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Every configured notifier. A failing notifier is logged and never
+/// stops the run or the other notifiers
+#[derive(Default)]
+pub struct Notifiers {
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl Notifiers {
+    /// The backends enabled in the context's notify config, none unless
+    /// `notify.enabled` is set
+    pub fn from_context(ctx: &AppContext) -> Result<Self, String> {
+        let (config, notify) = (&ctx.config, &ctx.notify);
+        let mut notifiers = Notifiers::default();
+        if !config.notify.enabled {
+            return Ok(notifiers);
+        }
+        let registry = &config.urls.registry;
+
+        if notify.uses(Backend::Telegram) {
+            if let Some(telegram) = &notify.telegram {
+                notifiers.push(telegram::TelegramNotifier::from_config(
+                    telegram,
+                    registry,
+                    &ctx.client,
+                )?);
+            }
+        }
+        if notify.uses(Backend::Webhook) {
+            notifiers.push(webhook::WebhookNotifier::from_config(
+                &notify.webhooks,
+                registry,
+                &ctx.client,
+            )?);
+        }
+        if notify.uses(Backend::Ntfy) {
+            if let Some(ntfy) = &notify.ntfy {
+                notifiers.push(ntfy::NtfyNotifier::from_config(
+                    ntfy,
+                    registry,
+                    &ctx.client,
+                )?);
+            }
+        }
+        if notify.uses(Backend::Email) {
+            if let Some(email) = &notify.email {
+                let email = EmailConfig {
+                    state: ctx.path(&email.state),
+                    ..email.clone()
+                };
+                notifiers.push(email::EmailNotifier::from_config(&email, registry)?);
+            }
+        }
+        if notify.uses(Backend::Discord) {
+            notifiers.push(discord::DiscordNotifier::new(ctx));
+        }
+
+        Ok(notifiers)
+    }
+
+    pub fn push(&mut self, notifier: impl Notifier + 'static) {
+        self.notifiers.push(Box::new(notifier));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notifiers.is_empty()
+    }
+
+    /// Send `event` to every notifier, returning the failures as
+    /// `<name>: <error>` once they are logged
+    pub async fn emit(&mut self, event: Event<'_>) -> Vec<String> {
+        let mut errors = Vec::new();
+        for notifier in &mut self.notifiers {
+            if let Err(e) = notifier.notify(&event).await {
+                eprintln!("{} notifier failed: {}", notifier.name(), e);
+                errors.push(format!("{}: {}", notifier.name(), e));
+            }
+        }
+        errors
+    }
+
+    pub async fn finish(&mut self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for notifier in &mut self.notifiers {
+            if let Err(e) = notifier.finish().await {
+                errors.push(format!("{}: {}", notifier.name(), e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+/// Cover shown for a batch of new books: the first book's, or the series
+/// cover when there is none
+pub(crate) fn first_cover<'a>(entry: &'a WatchlistEntry, covers: &[&'a str]) -> &'a str {
+    covers
+        .iter()
+        .copied()
+        .find(|cover| !cover.is_empty())
+        .unwrap_or(&entry.cover)
+}
+
+/// Placeholder for empty registry cells
+pub(crate) fn or_dash(value: &str) -> &str {
+    if value.is_empty() {
+        "-"
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder {
+        seen: Arc<Mutex<Vec<String>>>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl Notifier for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        async fn notify(&mut self, event: &Event<'_>) -> Result<(), String> {
+            let name = match event {
+                Event::RunStarted { .. } => "started",
+                Event::EntryProcessed { .. } => "processed",
+                Event::EntryFailed { .. } => "failed",
+                Event::RunFinished { .. } => "finished",
+                Event::ScraperBroken { .. } => "broken",
+            };
+            self.seen.lock().unwrap().push(name.to_string());
+            if self.fail {
+                Err("boom".to_string())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_notifier_does_not_stop_others() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut notifiers = Notifiers::default();
+        notifiers.push(Recorder {
+            seen: seen.clone(),
+            fail: true,
+        });
+        notifiers.push(Recorder {
+            seen: seen.clone(),
+            fail: false,
+        });
+
+        let errors = notifiers
+            .emit(Event::RunStarted {
+                id: 1,
+                entries: 0,
+                test: false,
+            })
+            .await;
+        assert_eq!(errors, vec!["recorder: boom"]);
+        let stats = RunStats::default();
+        notifiers
+            .emit(Event::RunFinished {
+                stats: &stats,
+                error: None,
+                next_run: 0,
+            })
+            .await;
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["started", "started", "finished", "finished"]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use super::{first_cover, Event, Notifier};
+use crate::{
+    config::notify::NtfyConfig,
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::url::registry_search_url,
+};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+
+/// Books listed in the message before it says "and N more"
+const MAX_LISTED: usize = 10;
+
+/// https://docs.ntfy.sh/publish/#publish-as-json
+#[derive(Serialize, Debug)]
+struct Message<'a> {
+    topic: &'a str,
+    title: String,
+    message: String,
+    tags: Vec<String>,
+    click: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attach: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u8>,
+}
+
+/// Pushes one phone notification per entry with new books to an ntfy topic
+pub struct NtfyNotifier {
+    client: Client,
+    server: String,
+    topic: String,
+    token: Option<String>,
+    priority: Option<u8>,
+    registry: String,
+}
+
+impl NtfyNotifier {
+    pub fn from_config(
+        config: &NtfyConfig,
+        registry: &str,
+        client: &Client,
+    ) -> Result<Self, String> {
+        Ok(NtfyNotifier {
+            client: client.clone(),
+            server: config.server.trim_end_matches('/').to_string(),
+            topic: config.topic.clone(),
+            token: config.token()?,
+            priority: config.priority,
+            registry: registry.to_string(),
+        })
+    }
+
+    fn message<'a>(
+        &'a self,
+        entry: &WatchlistEntry,
+        books: &[&BookRow],
+        cover: &'a str,
+    ) -> Message<'a> {
+        let mut lines: Vec<String> = books
+            .iter()
+            .take(MAX_LISTED)
+            .map(|book| {
+                if book.isbn.is_empty() {
+                    book.title.clone()
+                } else {
+                    format!("{} (ISBN {})", book.title, book.isbn)
+                }
+            })
+            .collect();
+        if books.len() > MAX_LISTED {
+            lines.push(format!("and {} more", books.len() - MAX_LISTED));
+        }
+
+        // Tags matching an emoji short code show up as that emoji
+        let mut tags = vec!["books".to_string()];
+        tags.extend(entry.tags.iter().cloned());
+
+        Message {
+            topic: &self.topic,
+            title: format!("{}: {} new book(s)", entry.name, books.len()),
+            message: lines.join("\n"),
+            tags,
+            click: registry_search_url(&self.registry, &entry.name),
+            attach: (!cover.is_empty()).then_some(cover),
+            priority: self.priority,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for NtfyNotifier {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    async fn notify(&mut self, event: &Event<'_>) -> Result<(), String> {
+        let Event::EntryProcessed {
+            entry,
+            new_books,
+            covers,
+            ..
+        } = *event
+        else {
+            return Ok(());
+        };
+        if new_books.is_empty() {
+            return Ok(());
+        }
+
+        // JSON publishing keeps non-ASCII titles out of the headers
+        let mut request = self.client.post(&self.server).json(&self.message(
+            entry,
+            new_books,
+            first_cover(entry, covers),
+        ));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("request failed: {}", e))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            let body = response.text().await.unwrap_or_default();
+            Err(format!("ntfy returned {}: {}", status, body))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::app::AppConfig;
+    use std::collections::HashMap;
+    use wiremock::{
+        matchers::{body_json, header, method},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn test_publishes_entry_with_cover() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("Authorization", "Bearer tk_test"))
+            .and(body_json(serde_json::json!({
+                "topic": "vbt",
+                "title": "Tên Sách: 1 new book(s)",
+                "message": "Tên Sách 1 (ISBN 978-604)",
+                "tags": ["books", "ln"],
+                "click": "https://ppdvn.gov.vn/web/guest/ke-hoach-xuat-ban?query=Tên+Sách",
+                "attach": "https://example.com/cover.jpg"
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut notifier = NtfyNotifier::from_config(
+            &NtfyConfig {
+                server: server.uri(),
+                topic: "vbt".to_string(),
+                token: Some("tk_test".to_string()),
+                priority: None,
+            },
+            &AppConfig::default().urls.registry,
+            &Client::new(),
+        )
+        .unwrap();
+        let entry = WatchlistEntry {
+            name: "Tên Sách".to_string(),
+            cover: "https://example.com/cover.jpg".to_string(),
+            other: HashMap::new(),
+            tags: vec!["ln".to_string()],
+            ..Default::default()
+        };
+        let book = BookRow::sample("978-604", "Tên Sách 1");
+
+        notifier
+            .notify(&Event::EntryProcessed {
+                entry: &entry,
+                rows: 1,
+                new_books: &[&book],
+                covers: &[],
+            })
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use super::{first_cover, or_dash, Event, Notifier};
+use crate::{
+    config::notify::TelegramConfig,
+    telegram::send::{escape_html, TelegramBot},
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::url::registry_search_url,
+};
+use async_trait::async_trait;
+use reqwest::Client;
+
+/// Posts the cover and the new volumes of an entry to every configured chat
+pub struct TelegramNotifier {
+    bot: TelegramBot,
+    chats: Vec<String>,
+    /// Registry search page, see `urls.registry`
+    registry: String,
+}
+
+impl TelegramNotifier {
+    pub fn new(bot: TelegramBot, chats: Vec<String>, registry: impl Into<String>) -> Self {
+        TelegramNotifier {
+            bot,
+            chats,
+            registry: registry.into(),
+        }
+    }
+
+    pub fn from_config(
+        config: &TelegramConfig,
+        registry: &str,
+        client: &Client,
+    ) -> Result<Self, String> {
+        let bot = TelegramBot::new(config.token()?).client(client.clone());
+        let bot = match &config.api_url {
+            Some(api_url) => bot.api_url(api_url),
+            None => bot,
+        };
+        Ok(TelegramNotifier::new(bot, config.chats.clone(), registry))
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    async fn notify(&mut self, event: &Event<'_>) -> Result<(), String> {
+        let Event::EntryProcessed {
+            entry,
+            new_books,
+            covers,
+            ..
+        } = *event
+        else {
+            return Ok(());
+        };
+        if new_books.is_empty() {
+            return Ok(());
+        }
+
+        let caption = new_books_caption(&self.registry, entry, new_books);
+        let cover = first_cover(entry, covers);
+        let mut errors = Vec::new();
+        for chat in &self.chats {
+            if let Err(e) = self.bot.send_with_photo(chat, cover, &caption).await {
+                errors.push(format!("chat {}: {}", chat, e));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+}
+
+/// Caption listing every new or changed book of an entry
+fn new_books_caption(registry: &str, entry: &WatchlistEntry, books: &[&BookRow]) -> String {
+    let mut lines = vec![
+        format!("<b>{}</b>", escape_html(&entry.name)),
+        format!("{} new or updated book(s)", books.len()),
+    ];
+    for book in books {
+        lines.push(String::new());
+        lines.push(format!("• <b>{}</b>", escape_html(&book.title)));
+        lines.push(format!(
+            "ISBN: {} | Translator: {}",
+            escape_html(or_dash(&book.isbn)),
+            escape_html(or_dash(&book.translator))
+        ));
+        lines.push(format!(
+            "Print run: {} | Partner: {}",
+            escape_html(or_dash(&book.quantity)),
+            escape_html(or_dash(&book.partner))
+        ));
+    }
+    lines.push(String::new());
+    lines.push(format!(
+        "<a href=\"{}\">Registry</a>",
+        escape_html(&registry_search_url(registry, &entry.name))
+    ));
+
+    lines.join("\n")
+}
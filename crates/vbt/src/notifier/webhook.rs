@@ -0,0 +1,296 @@
+use super::{Event, Notifier};
+use crate::{
+    config::notify::WebhookConfig,
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::url::registry_search_url,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+
+/// Bumped whenever a field is renamed or removed, adding fields keeps it
+pub const SCHEMA_VERSION: u32 = 1;
+/// `sha256=<hex>` HMAC of the raw body, keyed with the webhook secret
+pub const SIGNATURE_HEADER: &str = "X-VBT-Signature";
+pub const EVENT_HEADER: &str = "X-VBT-Event";
+pub const NEW_BOOK_EVENT: &str = "book.new";
+
+/// The document posted for every new or changed book
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NewBook {
+    pub schema_version: u32,
+    pub event: String,
+    pub run_id: i64,
+    /// RFC 3339
+    pub detected_at: String,
+    pub entry: EntryInfo,
+    pub book: BookInfo,
+    pub registry_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EntryInfo {
+    pub name: String,
+    pub slug: String,
+    pub cover: String,
+    pub tags: Vec<String>,
+    /// Alternative titles keyed by language, e.g. `romaji`
+    pub other: BTreeMap<String, String>,
+}
+
+/// A registry row with stable field names, independent of [`BookRow`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BookInfo {
+    pub isbn: String,
+    pub title: String,
+    pub author: String,
+    pub translator: String,
+    pub print_run: String,
+    pub self_published: String,
+    pub partner: String,
+    pub registration_number: String,
+    /// Cover of this volume, the series cover when none was found
+    pub cover: String,
+}
+
+impl NewBook {
+    pub fn new(
+        run_id: i64,
+        entry: &WatchlistEntry,
+        book: &BookRow,
+        cover: &str,
+        registry: &str,
+    ) -> Self {
+        NewBook {
+            schema_version: SCHEMA_VERSION,
+            event: NEW_BOOK_EVENT.to_string(),
+            run_id,
+            detected_at: Utc::now().to_rfc3339(),
+            entry: EntryInfo {
+                name: entry.name.clone(),
+                slug: entry.slug(),
+                cover: entry.cover.clone(),
+                tags: entry.tags.clone(),
+                other: entry
+                    .other
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            },
+            book: BookInfo {
+                isbn: book.isbn.clone(),
+                title: book.title.clone(),
+                author: book.author.clone(),
+                translator: book.translator.clone(),
+                print_run: book.quantity.clone(),
+                self_published: book.self_published.clone(),
+                partner: book.partner.clone(),
+                registration_number: book.registration_number.clone(),
+                cover: cover.to_string(),
+            },
+            registry_url: registry_search_url(registry, &book.title),
+        }
+    }
+}
+
+/// Value of [`SIGNATURE_HEADER`] for `body`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+struct Hook {
+    url: String,
+    secret: Option<String>,
+}
+
+/// POSTs a [`NewBook`] document per new book to every configured url
+pub struct WebhookNotifier {
+    client: Client,
+    hooks: Vec<Hook>,
+    run_id: i64,
+    registry: String,
+}
+
+impl WebhookNotifier {
+    pub fn from_config(
+        configs: &[WebhookConfig],
+        registry: &str,
+        client: &Client,
+    ) -> Result<Self, String> {
+        let hooks = configs
+            .iter()
+            .map(|config| {
+                Ok(Hook {
+                    url: config.url()?,
+                    secret: config.secret()?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(WebhookNotifier {
+            client: client.clone(),
+            hooks,
+            run_id: 0,
+            registry: registry.to_string(),
+        })
+    }
+
+    async fn post(&self, hook: &Hook, body: &[u8]) -> Result<(), String> {
+        let mut request = self
+            .client
+            .post(&hook.url)
+            .header("Content-Type", "application/json")
+            .header(EVENT_HEADER, NEW_BOOK_EVENT)
+            .body(body.to_vec());
+        if let Some(secret) = &hook.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, body));
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("request failed: {}", e.without_url()))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(format!("webhook returned {}", status))
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "Webhook"
+    }
+
+    async fn notify(&mut self, event: &Event<'_>) -> Result<(), String> {
+        let (entry, new_books, covers) = match *event {
+            Event::RunStarted { id, .. } => {
+                self.run_id = id;
+                return Ok(());
+            }
+            Event::EntryProcessed {
+                entry,
+                new_books,
+                covers,
+                ..
+            } => (entry, new_books, covers),
+            _ => return Ok(()),
+        };
+
+        let mut errors = Vec::new();
+        for (i, book) in new_books.iter().enumerate() {
+            let cover = covers.get(i).copied().unwrap_or(&entry.cover);
+            let document = NewBook::new(self.run_id, entry, book, cover, &self.registry);
+            let body = serde_json::to_vec(&document)
+                .map_err(|e| format!("Failed to serialize {}: {}", book.title, e))?;
+            for (i, hook) in self.hooks.iter().enumerate() {
+                if let Err(e) = self.post(hook, &body).await {
+                    // The url may hold a token, name the hook by position
+                    errors.push(format!("webhook #{}: {}", i + 1, e));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::app::AppConfig;
+    use std::collections::HashMap;
+    use wiremock::{
+        matchers::{header, method},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_posts_signed_document_per_book() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header(EVENT_HEADER, NEW_BOOK_EVENT))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let mut notifier = WebhookNotifier::from_config(
+            &[WebhookConfig {
+                url: server.uri(),
+                secret: Some("s3cret".to_string()),
+            }],
+            &AppConfig::default().urls.registry,
+            &Client::new(),
+        )
+        .unwrap();
+
+        let entry = WatchlistEntry {
+            name: "Entry".to_string(),
+            cover: "https://example.com/cover.jpg".to_string(),
+            other: HashMap::from([("romaji".to_string(), "Entry Romaji".to_string())]),
+            tags: vec!["ln".to_string()],
+            ..Default::default()
+        };
+        let book = |isbn: &str| BookRow {
+            quantity: "2000".to_string(),
+            ..BookRow::sample(isbn, &format!("Entry {}", isbn))
+        };
+        let books = [book("1"), book("2")];
+        let new_books: Vec<&BookRow> = books.iter().collect();
+
+        notifier
+            .notify(&Event::RunStarted {
+                id: 7,
+                entries: 1,
+                test: false,
+            })
+            .await
+            .unwrap();
+        notifier
+            .notify(&Event::EntryProcessed {
+                entry: &entry,
+                rows: 2,
+                new_books: &new_books,
+                covers: &[&entry.cover, "https://example.com/2.jpg"],
+            })
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        for request in &requests {
+            let signature = request.headers.get(SIGNATURE_HEADER).unwrap();
+            assert_eq!(signature.to_str().unwrap(), sign("s3cret", &request.body));
+        }
+        let document: NewBook = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(document.schema_version, SCHEMA_VERSION);
+        assert_eq!(document.run_id, 7);
+        assert_eq!(document.entry.slug, "Entry_Romaji");
+        assert_eq!(document.book.isbn, "2");
+        assert_eq!(document.book.print_run, "2000");
+        assert_eq!(document.book.cover, "https://example.com/2.jpg");
+    }
+}
@@ -1,49 +1,117 @@
-use crate::types::book::BookRow;
+use crate::{
+    context::AppContext,
+    types::{book::BookRow, watchlist::WatchlistEntry},
+    utils::cache::generate_cache_key,
+};
 use reqwest::Client;
 use scraper::{Html, Selector};
+use serde::Serialize;
+use std::collections::HashSet;
 
-async fn get_data_fetch(query: &str, page: u8) -> Result<String, Box<dyn std::error::Error>> {
+/// Registry requests made for a query or an entry
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct FetchStats {
+    pub requests: usize,
+    /// Requests repeated because the page had no table or a server error
+    pub retries: usize,
+}
+
+/// The registry answered every attempt with a page that has no results
+/// table, e.g. a block page or a new layout
+#[derive(Debug, thiserror::Error)]
+#[error("Table not found after {attempts} attempts")]
+pub struct TableNotFound {
+    pub attempts: u32,
+}
+
+async fn get_data_fetch(
+    client: &Client,
+    registry: &str,
+    query: &str,
+    page: u8,
+) -> Result<String, Box<dyn std::error::Error>> {
     let query_clean = query.trim_end().replace(" ", "+");
     // https://ppdvn.gov.vn/web/guest/ke-hoach-xuat-ban?query=H%C3%A0nh+Tr%C3%ACnh+C%E1%BB%A7a+Elaina&p=1
-    let url = format!(
-        "https://ppdvn.gov.vn/web/guest/ke-hoach-xuat-ban?query={}&p={}",
-        query_clean, page
-    );
-    let client = Client::new();
-    let res = client.get(url).send().await?;
+    let url = format!("{}?query={}&p={}", registry, query_clean, page);
+    let res = client.get(url).send().await?.error_for_status()?;
     let body = res.text().await?;
     Ok(body)
 }
 
-pub async fn extract_table_data(query: &str) -> Result<Vec<BookRow>, Box<dyn std::error::Error>> {
-    const MAX_RETRIES: u32 = 3;
-
-    for attempt in 1..=MAX_RETRIES {
-        match try_extract_table_data(query).await {
-            Ok(rows) => return Ok(rows),
-            Err(e) => {
-                if e.to_string().contains("Table not found") {
-                    println!("Attempt {}: Table not found, retrying...", attempt);
-                    #[allow(unused_assignments)]
-                    if attempt < MAX_RETRIES {
-                        tokio::time::sleep(tokio::time::Duration::from_millis(5000)).await;
-                    }
-                } else {
-                    return Err(e);
-                }
-            }
+pub async fn extract_table_data(
+    ctx: &AppContext,
+    query: &str,
+) -> Result<Vec<BookRow>, Box<dyn std::error::Error>> {
+    fetch_table_data(ctx, query, &mut FetchStats::default()).await
+}
+
+/// [`extract_table_data`], counting the requests in `stats`
+pub async fn fetch_table_data(
+    ctx: &AppContext,
+    query: &str,
+    stats: &mut FetchStats,
+) -> Result<Vec<BookRow>, Box<dyn std::error::Error>> {
+    let config = &ctx.config;
+    let max_retries = config.fetch.retries;
+
+    for attempt in 1..=max_retries {
+        stats.requests += 1;
+        if attempt > 1 {
+            stats.retries += 1;
+            tokio::time::sleep(tokio::time::Duration::from_millis(
+                config.fetch.retry_delay_ms,
+            ))
+            .await;
+        }
+        let error: Box<dyn std::error::Error> =
+            match try_extract_table_data(&ctx.client, &config.urls.registry, query).await {
+                Ok(Some(rows)) => return Ok(rows),
+                Ok(None) => Box::new(TableNotFound { attempts: attempt }),
+                Err(e) if is_server_error(e.as_ref()) => e,
+                Err(e) => return Err(e),
+            };
+        if attempt == max_retries {
+            return Err(error);
         }
+        println!("Attempt {}: {}, retrying...", attempt, error);
     }
+    unreachable!("fetch.retries is at least 1")
+}
 
-    println!(
-        "Table not found after {} attempts, continuing with empty result",
-        MAX_RETRIES
-    );
-    Ok(Vec::new())
+/// A 5xx from the registry, worth another attempt
+fn is_server_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        .is_some_and(|status| status.is_server_error())
+}
+
+/// Rows for every query of `entry`. A book found by several queries is
+/// only kept once
+pub async fn extract_entry_data(
+    ctx: &AppContext,
+    entry: &WatchlistEntry,
+    stats: &mut FetchStats,
+) -> Result<Vec<BookRow>, Box<dyn std::error::Error>> {
+    let mut rows = Vec::new();
+    let mut seen = HashSet::new();
+    for query in entry.queries() {
+        for row in fetch_table_data(ctx, query, stats).await? {
+            if seen.insert(generate_cache_key(&row)) {
+                rows.push(row);
+            }
+        }
+    }
+    Ok(rows)
 }
 
-async fn try_extract_table_data(query: &str) -> Result<Vec<BookRow>, Box<dyn std::error::Error>> {
-    let html = get_data_fetch(query, 1).await?;
+/// The rows of the results table, `None` if the page has no table
+async fn try_extract_table_data(
+    client: &Client,
+    registry: &str,
+    query: &str,
+) -> Result<Option<Vec<BookRow>>, Box<dyn std::error::Error>> {
+    let html = get_data_fetch(client, registry, query, 1).await?;
     let document = Html::parse_document(&html);
     let selectors = (
         Selector::parse("#list_data_return table")
@@ -52,10 +120,9 @@ async fn try_extract_table_data(query: &str) -> Result<Vec<BookRow>, Box<dyn std
         Selector::parse("td").map_err(|e| format!("Invalid cell selector: {}", e))?,
     );
 
-    let table = document
-        .select(&selectors.0)
-        .next()
-        .ok_or("Table not found")?;
+    let Some(table) = document.select(&selectors.0).next() else {
+        return Ok(None);
+    };
 
     let rows = table
         .select(&selectors.1)
@@ -81,5 +148,80 @@ async fn try_extract_table_data(query: &str) -> Result<Vec<BookRow>, Box<dyn std
             }
         })
         .collect();
-    Ok(rows)
+    Ok(Some(rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{app::AppConfig, notify::NotifyConfig},
+        services::run_report::error_kind,
+    };
+    use wiremock::{
+        matchers::{method, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn context(server: &MockServer) -> AppContext {
+        let mut config = AppConfig::default();
+        config.urls.registry = server.uri();
+        config.fetch.retries = 2;
+        config.fetch.retry_delay_ms = 0;
+        AppContext::new(config, NotifyConfig::default())
+    }
+
+    #[tokio::test]
+    async fn test_no_table() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("query", "Blocked"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html>Access denied</html>"))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let ctx = context(&server).await;
+
+        let mut stats = FetchStats::default();
+        let e = fetch_table_data(&ctx, "Blocked", &mut stats)
+            .await
+            .unwrap_err();
+        assert_eq!(error_kind(e.as_ref()), "no_table");
+        assert_eq!(e.to_string(), "Table not found after 2 attempts");
+        assert_eq!(
+            stats,
+            FetchStats {
+                requests: 2,
+                retries: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("query", "Down"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("query", "Gone"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let ctx = context(&server).await;
+
+        let e = fetch_table_data(&ctx, "Down", &mut FetchStats::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error_kind(e.as_ref()), "status");
+        // Only server errors are retried
+        let e = fetch_table_data(&ctx, "Gone", &mut FetchStats::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error_kind(e.as_ref()), "status");
+    }
 }
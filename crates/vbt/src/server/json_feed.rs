@@ -0,0 +1,59 @@
+use rss::read::Channel;
+use serde_json::{json, Map, Value};
+
+const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// Render a channel read back from RSS as a JSON Feed 1.1 published at
+/// `feed_url`. Image enclosures double as the item image
+pub fn to_json_feed(channel: &Channel, feed_url: &str) -> Value {
+    let items: Vec<Value> = channel
+        .items
+        .iter()
+        .map(|item| {
+            let mut value = Map::new();
+            value.insert("id".to_string(), json!(item.guid));
+            value.insert("url".to_string(), json!(item.link));
+            value.insert("title".to_string(), json!(item.title));
+            value.insert("content_text".to_string(), json!(item.description));
+            if let Some(date) = item.pub_date {
+                value.insert("date_published".to_string(), json!(date.to_rfc3339()));
+            }
+            if let Some(creator) = item.dc_creator.as_ref().or(item.author.as_ref()) {
+                value.insert("authors".to_string(), json!([{ "name": creator }]));
+            }
+            if !item.categories.is_empty() {
+                value.insert("tags".to_string(), json!(item.categories));
+            }
+            if let Some(enclosure) = &item.enclosure {
+                if enclosure.mime_type.starts_with("image/") {
+                    value.insert("image".to_string(), json!(enclosure.url));
+                }
+                value.insert(
+                    "attachments".to_string(),
+                    json!([{
+                        "url": enclosure.url,
+                        "mime_type": enclosure.mime_type,
+                        "size_in_bytes": enclosure.length,
+                    }]),
+                );
+            }
+            Value::Object(value)
+        })
+        .collect();
+
+    let mut feed = json!({
+        "version": VERSION,
+        "title": channel.title,
+        "home_page_url": channel.link,
+        "feed_url": feed_url,
+        "description": channel.description,
+        "items": items,
+    });
+    if let Some(image) = &channel.image {
+        feed["icon"] = json!(image.url);
+    }
+    if let Some(language) = &channel.language {
+        feed["language"] = json!(language);
+    }
+    feed
+}
@@ -0,0 +1,305 @@
+//! `vbt serve`: the feeds and a read-only JSON API over HTTP
+pub mod json_feed;
+pub mod snapshot;
+
+use crate::services::cover_resolver::normalize_isbn;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use snapshot::Snapshot;
+use std::sync::{Arc, RwLock};
+
+/// The snapshot handlers read from, replaced after every refresh
+pub type SharedSnapshot = Arc<RwLock<Arc<Snapshot>>>;
+
+/// A response body with what clients need to cache it
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    body: Bytes,
+    content_type: &'static str,
+    etag: String,
+    /// Whole seconds, the precision of `Last-Modified`
+    last_modified: DateTime<Utc>,
+}
+
+impl Document {
+    pub fn new(
+        body: impl Into<Bytes>,
+        content_type: &'static str,
+        last_modified: DateTime<Utc>,
+    ) -> Self {
+        let body = body.into();
+        let hash = hex::encode(Sha256::digest(&body));
+        Document {
+            etag: format!("\"{}\"", &hash[..32]),
+            body,
+            content_type,
+            last_modified: last_modified.trunc_subsecs(0),
+        }
+    }
+
+    pub fn json(value: &impl Serialize, last_modified: DateTime<Utc>) -> Result<Self, String> {
+        let body = serde_json::to_vec(value).map_err(|e| e.to_string())?;
+        Ok(Document::new(body, "application/json", last_modified))
+    }
+
+    /// 304 when the client's copy is current, going by `If-None-Match`
+    /// first and `If-Modified-Since` only without it
+    fn respond(&self, headers: &HeaderMap) -> Response {
+        let not_modified = match headers.get(header::IF_NONE_MATCH) {
+            Some(tags) => tags.to_str().is_ok_and(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim().trim_start_matches("W/"))
+                    .any(|tag| tag == "*" || tag == self.etag)
+            }),
+            None => headers
+                .get(header::IF_MODIFIED_SINCE)
+                .and_then(|since| since.to_str().ok())
+                .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+                .is_some_and(|since| since >= self.last_modified),
+        };
+
+        let last_modified = self
+            .last_modified
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        let mut response = if not_modified {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(self.content_type),
+                )],
+                self.body.clone(),
+            )
+                .into_response()
+        };
+        let headers = response.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Ok(last_modified) = HeaderValue::from_str(&last_modified) {
+            headers.insert(header::LAST_MODIFIED, last_modified);
+        }
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=300"),
+        );
+        response
+    }
+}
+
+pub fn router(snapshot: SharedSnapshot) -> Router {
+    Router::new()
+        .route("/feeds/{file}", get(feed))
+        .route("/opml", get(opml))
+        .route("/api/entries", get(entries))
+        .route("/api/entries/{slug}/books", get(entry_books))
+        .route("/api/books", get(books))
+        .with_state(snapshot)
+}
+
+fn current(snapshot: &SharedSnapshot) -> Arc<Snapshot> {
+    snapshot
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+fn not_found(what: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("{} not found", what) })),
+    )
+        .into_response()
+}
+
+/// `<slug>.rss`, `<slug>.atom` or `<slug>.json`
+async fn feed(
+    State(snapshot): State<SharedSnapshot>,
+    Path(file): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    match current(&snapshot).feeds.get(&file) {
+        Some(document) => document.respond(&headers),
+        None => not_found(&file),
+    }
+}
+
+async fn opml(State(snapshot): State<SharedSnapshot>, headers: HeaderMap) -> Response {
+    match &current(&snapshot).opml {
+        Some(document) => document.respond(&headers),
+        None => not_found("OPML"),
+    }
+}
+
+async fn entries(State(snapshot): State<SharedSnapshot>, headers: HeaderMap) -> Response {
+    current(&snapshot).entries.respond(&headers)
+}
+
+async fn entry_books(
+    State(snapshot): State<SharedSnapshot>,
+    Path(slug): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    match current(&snapshot).books.get(&slug) {
+        Some(document) => document.respond(&headers),
+        None => not_found(&slug),
+    }
+}
+
+#[derive(Deserialize)]
+struct BooksQuery {
+    isbn: Option<String>,
+}
+
+/// Every entry with a book of that ISBN, hyphens or not
+async fn books(
+    State(snapshot): State<SharedSnapshot>,
+    Query(query): Query<BooksQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(isbn) = query.isbn.filter(|isbn| !isbn.trim().is_empty()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "isbn is required" })),
+        )
+            .into_response();
+    };
+
+    let snapshot = current(&snapshot);
+    let found: Vec<Value> = snapshot
+        .isbns
+        .get(&normalize_isbn(&isbn))
+        .into_iter()
+        .flatten()
+        .map(|(slug, book)| json!({ "entry": slug, "book": book }))
+        .collect();
+    match Document::json(&found, snapshot.books_modified) {
+        Ok(document) => document.respond(&headers),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::app::AppConfig,
+        context::AppContext,
+        file_ops::{json_ops::save_json, rss_ops::generate_and_save_rss},
+        json::return_json::return_json,
+        types::{book::BookRow, watchlist::WatchlistEntry},
+    };
+    use reqwest::Client;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_serves_feeds_and_api() {
+        let (ctx, root) = AppContext::temp("serve", AppConfig::default());
+        fs::create_dir_all(root.path()).unwrap();
+        fs::write(
+            root.file("watchlist.json"),
+            r#"[{ "name": "Entry", "other": [], "cover": "" }]"#,
+        )
+        .unwrap();
+        let entry = WatchlistEntry {
+            name: "Entry".to_string(),
+            ..Default::default()
+        };
+        let book = BookRow {
+            quantity: "2000".to_string(),
+            ..BookRow::sample("978-604-1", "Entry 1")
+        };
+        let rows = vec![book];
+        generate_and_save_rss(&ctx, &rows, &entry, false).unwrap();
+        let json = return_json(&entry.name, &rows).unwrap();
+        save_json(&json, &ctx.path("feed/json/Entry.json")).unwrap();
+
+        let snapshot = Arc::new(RwLock::new(Arc::new(Snapshot::load(&ctx).await.unwrap())));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(snapshot)).await });
+        let client = Client::new();
+        let get = |path: &str| client.get(format!("{}{}", base, path));
+
+        let res = get("/feeds/Entry.rss").send().await.unwrap();
+        assert_eq!(res.status(), 200);
+        let etag = res.headers()["etag"].to_str().unwrap().to_string();
+        let last_modified = res.headers()["last-modified"].to_str().unwrap().to_string();
+        assert!(res.text().await.unwrap().contains("<title>Entry 1</title>"));
+
+        let res = get("/feeds/Entry.rss")
+            .header("If-None-Match", &etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 304);
+        let res = get("/feeds/Entry.rss")
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 304);
+        let res = get("/feeds/Entry.rss")
+            .header("If-None-Match", "\"other\"")
+            .header("If-Modified-Since", &last_modified)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 200);
+
+        let atom = get("/feeds/Entry.atom").send().await.unwrap();
+        assert_eq!(
+            atom.headers()["content-type"],
+            "application/atom+xml; charset=utf-8"
+        );
+        assert!(atom.text().await.unwrap().contains("<feed"));
+        let json_feed: Value = get("/feeds/Entry.json")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(json_feed["items"][0]["title"], "Entry 1");
+        assert_eq!(get("/feeds/Other.rss").send().await.unwrap().status(), 404);
+
+        let entries: Value = get("/api/entries")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(entries[0]["slug"], "Entry");
+        assert_eq!(entries[0]["books"], 1);
+        let books: Value = get("/api/entries/Entry/books")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(books[0]["print_run"], "2000");
+        let found: Value = get("/api/books?isbn=9786041")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(found, json!([{ "entry": "Entry", "book": books[0] }]));
+        assert_eq!(get("/api/books").send().await.unwrap().status(), 400);
+    }
+}
@@ -0,0 +1,176 @@
+use super::{json_feed::to_json_feed, Document};
+use crate::{
+    commands::rebuild::load_feed_json,
+    context::AppContext,
+    services::{anilist, cover_resolver::normalize_isbn, watchlist::load_watchlist},
+    types::book::BookRow,
+};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+/// Everything the server answers with, built from the files on disk and
+/// swapped whole after each refresh so a request never sees half of one
+#[derive(Default)]
+pub struct Snapshot {
+    /// By file name, `<slug>.rss`, `<slug>.atom` and `<slug>.json`
+    pub feeds: HashMap<String, Document>,
+    pub opml: Option<Document>,
+    pub entries: Document,
+    /// Rows of each entry by slug
+    pub books: HashMap<String, Document>,
+    /// `(slug, row)` by normalized ISBN
+    pub isbns: HashMap<String, Vec<(String, Value)>>,
+    /// Newest of the row files, the `Last-Modified` of ISBN lookups
+    pub books_modified: DateTime<Utc>,
+}
+
+impl Snapshot {
+    /// Read the watchlist, every feed in `paths.rss_dir` and the rows in
+    /// `paths.json_dir`. The watchlist is enriched from the AniList cache
+    pub async fn load(ctx: &AppContext) -> Result<Self, String> {
+        let config = &ctx.config;
+        let public_url = &config.serve.public_url;
+        let mut watchlist = load_watchlist(ctx)?;
+        if config.anilist.enabled {
+            // Nothing is written, the cache is the run's business
+            anilist::enrich(ctx, &mut watchlist, true).await?;
+        }
+
+        let mut snapshot = Snapshot {
+            books_modified: DateTime::UNIX_EPOCH,
+            ..Default::default()
+        };
+        let mut entries_modified = modified(&ctx.path(&config.paths.watchlist));
+
+        let rss_dir = ctx.path(&config.paths.rss_dir);
+        if let Ok(dir) = fs::read_dir(&rss_dir) {
+            for file in dir.flatten() {
+                let path = file.path();
+                let Some(slug) = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_suffix(".rss"))
+                else {
+                    continue;
+                };
+                let path = path.to_string_lossy();
+                snapshot.add_feed(slug, &path, public_url)?;
+            }
+        }
+
+        let mut entries = Vec::new();
+        for entry in &watchlist {
+            let slug = entry.slug();
+            let json_path = ctx.path(&config.paths.json_file(&slug));
+            let rows = match load_feed_json(ctx, &slug)? {
+                Some(feed_json) => feed_json.rows,
+                None => Vec::new(),
+            };
+
+            let books_modified = modified(&json_path);
+            snapshot.books_modified = snapshot.books_modified.max(books_modified);
+            entries_modified = entries_modified.max(books_modified);
+            for row in &rows {
+                snapshot
+                    .isbns
+                    .entry(normalize_isbn(&row.isbn))
+                    .or_default()
+                    .push((slug.clone(), book_json(row)));
+            }
+
+            let feed_url = |extension: &str| format!("{}/feeds/{}.{}", public_url, slug, extension);
+            entries.push(json!({
+                "name": entry.name,
+                "slug": slug,
+                "cover": entry.cover,
+                // Sorted, so the ETag only changes with the content
+                "other": entry.other.iter().collect::<BTreeMap<_, _>>(),
+                "tags": entry.tags,
+                "format": entry.format,
+                "status": entry.status,
+                "genres": entry.genres,
+                "books": rows.len(),
+                "feeds": {
+                    "rss": feed_url("rss"),
+                    "atom": feed_url("atom"),
+                    "json": feed_url("json"),
+                },
+            }));
+            let rows: Vec<Value> = rows.iter().map(book_json).collect();
+            snapshot
+                .books
+                .insert(slug, Document::json(&rows, books_modified)?);
+        }
+        snapshot.entries = Document::json(&entries, entries_modified)?;
+
+        let opml_path = ctx.path(&config.paths.opml);
+        if let Ok(opml) = fs::read(&opml_path) {
+            snapshot.opml = Some(Document::new(
+                opml,
+                "text/x-opml; charset=utf-8",
+                modified(&opml_path),
+            ));
+        }
+
+        Ok(snapshot)
+    }
+
+    /// The RSS file of `slug` as is, and converted to Atom and JSON Feed
+    fn add_feed(&mut self, slug: &str, path: &str, public_url: &str) -> Result<(), String> {
+        let rss = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let channel = rss::read::Channel::parse(&String::from_utf8_lossy(&rss))
+            .map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+        let last_modified = channel.last_build_date.unwrap_or_else(|| modified(path));
+
+        let atom_url = format!("{}/feeds/{}.atom", public_url, slug);
+        let atom = rss::atom::build(&channel, &atom_url)
+            .map_err(|e| format!("Failed to build Atom for {}: {}", slug, e))?;
+        let json_feed = to_json_feed(&channel, &format!("{}/feeds/{}.json", public_url, slug));
+
+        self.feeds.insert(
+            format!("{}.rss", slug),
+            Document::new(rss, "application/rss+xml; charset=utf-8", last_modified),
+        );
+        self.feeds.insert(
+            format!("{}.atom", slug),
+            Document::new(atom, "application/atom+xml; charset=utf-8", last_modified),
+        );
+        self.feeds.insert(
+            format!("{}.json", slug),
+            Document::new(
+                serde_json::to_vec_pretty(&json_feed).map_err(|e| e.to_string())?,
+                "application/feed+json",
+                last_modified,
+            ),
+        );
+        Ok(())
+    }
+}
+
+/// A row with the field names of the webhook documents
+fn book_json(row: &BookRow) -> Value {
+    json!({
+        "isbn": row.isbn,
+        "title": row.title,
+        "author": row.author,
+        "translator": row.translator,
+        "print_run": row.quantity,
+        "self_published": row.self_published,
+        "partner": row.partner,
+        "registration_number": row.registration_number,
+    })
+}
+
+/// When the file at `path` last changed, the epoch if it doesn't exist
+fn modified(path: &str) -> DateTime<Utc> {
+    Path::new(path)
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .map(DateTime::<Utc>::from)
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
@@ -0,0 +1,368 @@
+use crate::{
+    context::AppContext, file_ops::json_ops::read_json, types::watchlist::WatchlistEntry,
+    utils::fs::write_atomic, utils::time::generate_unix_timestamp,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, path::Path};
+
+const MEDIA_QUERY: &str = "query ($id: Int) { Media(id: $id) { id title { romaji english native } \
+    synonyms coverImage { extraLarge large } format status genres } }";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct MediaTitle {
+    pub romaji: Option<String>,
+    pub english: Option<String>,
+    pub native: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CoverImage {
+    pub extra_large: Option<String>,
+    pub large: Option<String>,
+}
+
+/// The fields of an AniList `Media` we use
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Media {
+    pub id: u64,
+    pub title: MediaTitle,
+    pub synonyms: Vec<String>,
+    pub cover_image: CoverImage,
+    /// e.g. `NOVEL`, `MANGA` or `ONE_SHOT`
+    pub format: Option<String>,
+    /// e.g. `RELEASING` or `FINISHED`
+    pub status: Option<String>,
+    pub genres: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedMedia {
+    /// Unix timestamp
+    fetched_at: i64,
+    media: Media,
+}
+
+/// What enrichment filled in for an entry
+#[derive(Debug, PartialEq)]
+pub struct Enriched {
+    pub name: String,
+    pub fields: Vec<&'static str>,
+}
+
+/// Fill in the entries that have an `anilist` id from AniList. Media are
+/// cached in `anilist.cache` for `anilist.ttl_hours`, a failed fetch falls
+/// back to the cached media however old it is. The cache is not written
+/// on a dry run
+pub async fn enrich(
+    ctx: &AppContext,
+    watchlist: &mut [WatchlistEntry],
+    dry_run: bool,
+) -> Result<Vec<Enriched>, String> {
+    let config = &ctx.config.anilist;
+    let cache_path = ctx.path(&config.cache);
+    let mut cache = read_cache(&cache_path)?;
+    let ttl = config.ttl_hours as i64 * 60 * 60;
+    let now = generate_unix_timestamp();
+    let mut fetched = false;
+    let mut enriched = Vec::new();
+
+    for entry in watchlist.iter_mut() {
+        let Some(id) = entry.anilist else {
+            continue;
+        };
+
+        let cached = cache
+            .get(&id)
+            .filter(|cached| now - cached.fetched_at < ttl);
+        let media = match cached {
+            Some(cached) => cached.media.clone(),
+            None => match fetch_media(ctx, id).await {
+                Ok(media) => {
+                    cache.insert(
+                        id,
+                        CachedMedia {
+                            fetched_at: now,
+                            media: media.clone(),
+                        },
+                    );
+                    fetched = true;
+                    media
+                }
+                Err(e) => {
+                    eprintln!("Failed to fetch AniList media {}: {}", id, e);
+                    match cache.get(&id) {
+                        Some(stale) => stale.media.clone(),
+                        None => continue,
+                    }
+                }
+            },
+        };
+
+        let fields = fill_entry(entry, &media);
+        if !fields.is_empty() {
+            enriched.push(Enriched {
+                name: entry.name.clone(),
+                fields,
+            });
+        }
+    }
+
+    if fetched && !dry_run {
+        write_cache(&cache_path, &cache)?;
+    }
+    Ok(enriched)
+}
+
+async fn fetch_media(ctx: &AppContext, id: u64) -> Result<Media, String> {
+    let res = ctx
+        .client
+        .post(&ctx.config.anilist.api_url)
+        .json(&json!({ "query": MEDIA_QUERY, "variables": { "id": id } }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = res.status();
+    let body: Value = res
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response ({}): {}", status, e))?;
+
+    // GraphQL reports errors in the body, usually along with a 4xx status
+    if let Some(errors) = body["errors"].as_array() {
+        let messages: Vec<&str> = errors
+            .iter()
+            .filter_map(|error| error["message"].as_str())
+            .collect();
+        return Err(messages.join(", "));
+    }
+    if !status.is_success() {
+        return Err(format!("HTTP {}", status));
+    }
+
+    let media = &body["data"]["Media"];
+    if media.is_null() {
+        return Err("No such media".to_string());
+    }
+    serde_json::from_value(media.clone()).map_err(|e| format!("Invalid media: {}", e))
+}
+
+/// Copy `media` into `entry`. Titles and the cover are only filled when
+/// missing, the rest always follows AniList. Returns the fields that changed
+fn fill_entry(entry: &mut WatchlistEntry, media: &Media) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    let mut synonyms = media.synonyms.clone();
+
+    let titles = [
+        ("native", &media.title.native),
+        ("romaji", &media.title.romaji),
+        ("english", &media.title.english),
+    ];
+    for (key, title) in titles {
+        let Some(title) = title else {
+            continue;
+        };
+        if entry.other.contains_key(key) {
+            continue;
+        }
+
+        // The feed files are named after the romaji title, a new one would
+        // move the feed. Keep it searchable as a synonym instead
+        let slug = entry.slug();
+        entry.other.insert(key.to_string(), title.clone());
+        if entry.slug() != slug {
+            entry.other.remove(key);
+            if !synonyms.contains(title) {
+                synonyms.push(title.clone());
+            }
+            continue;
+        }
+        fields.push(key);
+    }
+
+    if entry.cover.is_empty() {
+        if let Some(cover) = media
+            .cover_image
+            .extra_large
+            .as_ref()
+            .or(media.cover_image.large.as_ref())
+        {
+            entry.cover = cover.clone();
+            fields.push("cover");
+        }
+    }
+
+    let format = media.format.as_deref().map(|format| {
+        match format {
+            "NOVEL" => "ln",
+            "MANGA" => "manga",
+            "ONE_SHOT" => "oneshot",
+            other => other,
+        }
+        .to_lowercase()
+    });
+    let status = media.status.as_ref().map(|status| status.to_lowercase());
+
+    if entry.synonyms != synonyms {
+        entry.synonyms = synonyms;
+        fields.push("synonyms");
+    }
+    if entry.format != format {
+        entry.format = format;
+        fields.push("format");
+    }
+    if entry.status != status {
+        entry.status = status;
+        fields.push("status");
+    }
+    if entry.genres != media.genres {
+        entry.genres = media.genres.clone();
+        fields.push("genres");
+    }
+    fields
+}
+
+fn read_cache(path: &str) -> Result<BTreeMap<u64, CachedMedia>, String> {
+    if !Path::new(path).exists() {
+        return Ok(BTreeMap::new());
+    }
+    let data = read_json(path)?;
+    serde_json::from_str(&data).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+fn write_cache(path: &str, cache: &BTreeMap<u64, CachedMedia>) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(cache)
+        .map_err(|e| format!("Failed to serialize {}: {}", path, e))?;
+    write_atomic(path, data.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{app::AppConfig, notify::NotifyConfig},
+        context::TempDir,
+    };
+    use std::collections::HashMap;
+    use wiremock::{
+        matchers::{body_partial_json, method},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    /// A `Media` response in the shape graphql.anilist.co returns
+    const FIXTURE: &str = include_str!("../../tests/fixtures/anilist_media_101583.json");
+
+    async fn fixture_server(expected: u64) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "variables": { "id": 101583 } })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(FIXTURE, "application/json"))
+            .expect(expected)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn context(name: &str, server: &MockServer) -> (AppContext, TempDir) {
+        let mut config = AppConfig::default();
+        config.anilist.enabled = true;
+        config.anilist.api_url = server.uri();
+        AppContext::temp(&format!("anilist_{}", name), config)
+    }
+
+    fn entry() -> WatchlistEntry {
+        WatchlistEntry {
+            name: "Tôi là Nhện đấy, có sao không?".to_string(),
+            other: HashMap::from([("english".to_string(), "So I'm a Spider".to_string())]),
+            anilist: Some(101583),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fills_missing_fields() {
+        let server = fixture_server(1).await;
+        let (ctx, root) = context("fill", &server);
+        let mut watchlist = vec![entry(), WatchlistEntry::default()];
+        let slug = watchlist[0].slug();
+
+        let enriched = enrich(&ctx, &mut watchlist, false).await.unwrap();
+
+        let entry = &watchlist[0];
+        assert_eq!(
+            enriched,
+            vec![Enriched {
+                name: entry.name.clone(),
+                fields: vec!["native", "cover", "synonyms", "format", "status", "genres"],
+            }]
+        );
+        assert_eq!(entry.other["native"], "蜘蛛ですが、なにか？");
+        // Set by hand, kept
+        assert_eq!(entry.other["english"], "So I'm a Spider");
+        // Would rename the feed, kept as a synonym
+        assert!(!entry.other.contains_key("romaji"));
+        assert_eq!(entry.slug(), slug);
+        assert!(entry
+            .synonyms
+            .contains(&"Kumo desu ga, Nani ka?".to_string()));
+        assert_eq!(
+            entry.cover,
+            "https://s4.anilist.co/file/anilistcdn/media/manga/cover/large/bx101583.jpg"
+        );
+        assert_eq!(entry.format.as_deref(), Some("ln"));
+        assert_eq!(entry.status.as_deref(), Some("releasing"));
+        assert_eq!(
+            entry.genres,
+            vec!["Action", "Adventure", "Comedy", "Fantasy"]
+        );
+        assert!(root.path().join("anilist_cache.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_cache_ttl() {
+        // The second enrichment is served from the cache
+        let server = fixture_server(1).await;
+        let (ctx, _root) = context("ttl", &server);
+        enrich(&ctx, &mut [entry()], false).await.unwrap();
+        let mut watchlist = [entry()];
+        enrich(&ctx, &mut watchlist, false).await.unwrap();
+        assert_eq!(watchlist[0].format.as_deref(), Some("ln"));
+        server.verify().await;
+
+        // Expired, fetched again
+        let server = fixture_server(1).await;
+        let mut config = ctx.config.clone();
+        config.anilist.api_url = server.uri();
+        config.anilist.ttl_hours = 0;
+        let ctx = AppContext::new(config, NotifyConfig::default()).storage(ctx.storage);
+        enrich(&ctx, &mut [entry()], false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_stale_cache() {
+        let server = fixture_server(1).await;
+        let (ctx, _root) = context("stale", &server);
+        enrich(&ctx, &mut [entry()], false).await.unwrap();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+                "data": null,
+                "errors": [{ "message": "Too Many Requests.", "status": 429 }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let mut config = ctx.config.clone();
+        config.anilist.api_url = server.uri();
+        config.anilist.ttl_hours = 0;
+        let ctx = AppContext::new(config, NotifyConfig::default()).storage(ctx.storage);
+
+        let mut watchlist = [entry()];
+        enrich(&ctx, &mut watchlist, false).await.unwrap();
+        assert_eq!(watchlist[0].format.as_deref(), Some("ln"));
+    }
+}
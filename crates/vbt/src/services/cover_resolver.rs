@@ -0,0 +1,262 @@
+use crate::{
+    context::AppContext, file_ops::json_ops::read_json, types::book::BookRow,
+    utils::fs::write_atomic, utils::time::generate_unix_timestamp,
+};
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+/// ISBNs no provider had a cover for are asked again after this long
+const RETRY_MISSES_AFTER: i64 = 7 * 24 * 60 * 60;
+
+/// Finds the cover of a single volume by its ISBN
+#[async_trait]
+pub trait CoverProvider: Send + Sync {
+    /// Shown in logs when the lookup fails
+    fn name(&self) -> &'static str;
+
+    /// Url of the cover of `isbn` (digits only), `None` if there is none
+    async fn lookup(&self, client: &Client, isbn: &str) -> Result<Option<String>, String>;
+}
+
+/// Open Library covers, which answer 404 for unknown ISBNs with `default=false`
+pub struct OpenLibrary {
+    base: String,
+}
+
+impl OpenLibrary {
+    pub fn new(base: impl Into<String>) -> Self {
+        OpenLibrary { base: base.into() }
+    }
+}
+
+#[async_trait]
+impl CoverProvider for OpenLibrary {
+    fn name(&self) -> &'static str {
+        "Open Library"
+    }
+
+    async fn lookup(&self, client: &Client, isbn: &str) -> Result<Option<String>, String> {
+        let url = format!("{}/{}-L.jpg", self.base, isbn);
+        let res = client
+            .head(format!("{}?default=false", url))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        match res.status() {
+            status if status.is_success() => Ok(Some(url)),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => Err(format!("HTTP {}", status)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Lookup {
+    /// `None` when no provider had it
+    url: Option<String>,
+    /// Unix timestamp
+    checked_at: i64,
+}
+
+/// Picks the cover of each volume: the `covers.overrides` map first, then
+/// what a provider found for the ISBN, then the series cover. Lookups are
+/// kept in `covers.lookup_cache` so feeds can be built without the network
+#[derive(Default)]
+pub struct CoverResolver {
+    overrides: HashMap<String, String>,
+    lookups: BTreeMap<String, Lookup>,
+    providers: Vec<Box<dyn CoverProvider>>,
+    cache_path: String,
+    dirty: bool,
+}
+
+impl CoverResolver {
+    /// The overrides, earlier lookups and the provider set in `covers.lookup`
+    pub fn load(ctx: &AppContext) -> Result<Self, String> {
+        let config = &ctx.config.covers;
+        let overrides_path = ctx.path(&config.overrides);
+        let cache_path = ctx.path(&config.lookup_cache);
+
+        let overrides: HashMap<String, String> = read_map(&overrides_path)?;
+        let mut resolver = CoverResolver {
+            overrides: overrides
+                .into_iter()
+                .map(|(isbn, url)| (normalize_isbn(&isbn), url))
+                .collect(),
+            lookups: read_map(&cache_path)?,
+            cache_path,
+            ..Default::default()
+        };
+        if config.lookup == "openlibrary" {
+            resolver = resolver.provider(OpenLibrary::new(&config.lookup_url));
+        }
+        Ok(resolver)
+    }
+
+    /// Ask `provider` after the ones added before it
+    pub fn provider(mut self, provider: impl CoverProvider + 'static) -> Self {
+        self.providers.push(Box::new(provider));
+        self
+    }
+
+    /// Cover of `isbn`, `series_cover` when nothing better is known
+    pub fn resolve<'a>(&'a self, isbn: &str, series_cover: &'a str) -> &'a str {
+        let isbn = normalize_isbn(isbn);
+        if let Some(url) = self.overrides.get(&isbn) {
+            return url;
+        }
+        self.lookups
+            .get(&isbn)
+            .and_then(|lookup| lookup.url.as_deref())
+            .unwrap_or(series_cover)
+    }
+
+    /// Ask the providers for the covers of `books` that aren't overridden
+    /// or looked up yet. A failing provider is logged and asked again next
+    /// time. Returns how many covers were found
+    pub async fn lookup(&mut self, ctx: &AppContext, books: &[BookRow]) -> usize {
+        let now = generate_unix_timestamp();
+        let mut found = 0;
+
+        for book in books {
+            let isbn = normalize_isbn(&book.isbn);
+            if isbn.is_empty() || self.overrides.contains_key(&isbn) {
+                continue;
+            }
+            match self.lookups.get(&isbn) {
+                Some(Lookup { url: Some(_), .. }) => continue,
+                Some(lookup) if now - lookup.checked_at < RETRY_MISSES_AFTER => continue,
+                _ => {}
+            }
+
+            let mut url = None;
+            let mut failed = false;
+            for provider in &self.providers {
+                match provider.lookup(&ctx.client, &isbn).await {
+                    Ok(Some(found)) => {
+                        url = Some(found);
+                        break;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        eprintln!(
+                            "{} cover lookup failed for {}: {}",
+                            provider.name(),
+                            isbn,
+                            e
+                        );
+                        failed = true;
+                    }
+                }
+            }
+            // Only remember a miss when every provider actually answered
+            if url.is_none() && (failed || self.providers.is_empty()) {
+                continue;
+            }
+
+            found += url.is_some() as usize;
+            self.lookups.insert(
+                isbn,
+                Lookup {
+                    url,
+                    checked_at: now,
+                },
+            );
+            self.dirty = true;
+        }
+        found
+    }
+
+    /// Write the lookups if any were made
+    pub fn save(&mut self) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let data = serde_json::to_string_pretty(&self.lookups)
+            .map_err(|e| format!("Failed to serialize {}: {}", self.cache_path, e))?;
+        write_atomic(&self.cache_path, data.as_bytes())?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// `978-604-...` and `978604...` are the same ISBN
+pub(crate) fn normalize_isbn(isbn: &str) -> String {
+    isbn.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// A JSON object at `path`, empty if the file doesn't exist
+fn read_map<T: for<'de> Deserialize<'de> + Default>(path: &str) -> Result<T, String> {
+    if !Path::new(path).exists() {
+        return Ok(T::default());
+    }
+    let data = read_json(path)?;
+    serde_json::from_str(&data).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::app::AppConfig;
+    use std::fs;
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn test_resolve_order() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/9786040000002-L.jpg"))
+            .and(query_param("default", "false"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/9786040000003-L.jpg"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut config = AppConfig::default();
+        config.covers.lookup_url = server.uri();
+        let (ctx, root) = AppContext::temp("cover_resolver", config);
+        fs::create_dir_all(root.path()).unwrap();
+        fs::write(
+            root.file("cover_overrides.json"),
+            r#"{ "978-604-0000001": "https://example.com/vol1.jpg" }"#,
+        )
+        .unwrap();
+
+        let books = ["978-604-0000001", "978-604-0000002", "978-604-0000003"]
+            .map(|isbn| BookRow::sample(isbn, "Volume"));
+        let mut resolver = CoverResolver::load(&ctx).unwrap();
+        assert_eq!(resolver.lookup(&ctx, &books).await, 1);
+        resolver.save().unwrap();
+
+        // Loaded again, the lookups come from the cache
+        let mut resolver = CoverResolver::load(&ctx).unwrap();
+        assert_eq!(resolver.lookup(&ctx, &books).await, 0);
+        let series = "https://example.com/series.jpg";
+        assert_eq!(
+            resolver.resolve("9786040000001", series),
+            "https://example.com/vol1.jpg"
+        );
+        assert_eq!(
+            resolver.resolve("978-604-0000002", series),
+            format!("{}/9786040000002-L.jpg", server.uri())
+        );
+        assert_eq!(resolver.resolve("978-604-0000003", series), series);
+    }
+}
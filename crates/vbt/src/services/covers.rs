@@ -0,0 +1,253 @@
+use crate::{
+    context::AppContext,
+    file_ops::json_ops::read_json,
+    utils::{fs::write_atomic, url::get_mime_type},
+};
+use image::{ImageFormat, ImageReader};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, io::Cursor, path::Path};
+
+/// Covers by the url they were downloaded from, kept next to the files
+const INDEX_FILE: &str = "index.json";
+
+/// A cover downloaded into `paths.covers`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CachedCover {
+    /// File names in `paths.covers`
+    pub file: String,
+    pub thumbnail: String,
+    /// Detected from the bytes, not the url
+    pub mime_type: String,
+    pub length: u64,
+}
+
+/// Where a feed points for a cover
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverLink {
+    pub url: String,
+    /// Zero when unknown
+    pub length: u64,
+    pub mime_type: String,
+}
+
+/// The covers in `paths.covers`. Covers that were never downloaded are
+/// linked at their original url
+#[derive(Debug, Default)]
+pub struct Covers {
+    dir: String,
+    base: String,
+    thumbnail_size: u32,
+    index: BTreeMap<String, CachedCover>,
+}
+
+impl Covers {
+    /// Covers downloaded by earlier runs, none when `covers.enabled` is off.
+    /// Entries whose file is gone are dropped so they get downloaded again
+    pub fn load(ctx: &AppContext) -> Result<Self, String> {
+        let config = &ctx.config;
+        let dir = ctx.path(&config.paths.covers);
+        let mut covers = Covers {
+            base: config.urls.covers_base.clone(),
+            thumbnail_size: config.covers.thumbnail_size,
+            ..Default::default()
+        };
+        if !config.covers.enabled {
+            return Ok(covers);
+        }
+
+        let index_path = format!("{}/{}", dir, INDEX_FILE);
+        if Path::new(&index_path).exists() {
+            let data = read_json(&index_path)?;
+            let index: BTreeMap<String, CachedCover> = serde_json::from_str(&data)
+                .map_err(|e| format!("Failed to parse {}: {}", index_path, e))?;
+            covers.index = index
+                .into_iter()
+                .filter(|(_, cover)| {
+                    Path::new(&dir).join(&cover.file).exists()
+                        && Path::new(&dir).join(&cover.thumbnail).exists()
+                })
+                .collect();
+        }
+        covers.dir = dir;
+        Ok(covers)
+    }
+
+    pub fn get(&self, url: &str) -> Option<&CachedCover> {
+        self.index.get(url)
+    }
+
+    /// Our copy of the cover at `url` if there is one
+    pub fn link(&self, url: &str) -> CoverLink {
+        match self.get(url) {
+            Some(cover) => CoverLink {
+                url: format!("{}/{}", self.base, cover.file),
+                length: cover.length,
+                mime_type: cover.mime_type.clone(),
+            },
+            None => CoverLink {
+                url: url.to_string(),
+                length: 0,
+                mime_type: get_mime_type(url).to_string(),
+            },
+        }
+    }
+
+    /// Our thumbnail of the cover at `url`, or the cover itself
+    pub fn thumbnail_url(&self, url: &str) -> String {
+        match self.get(url) {
+            Some(cover) => format!("{}/{}", self.base, cover.thumbnail),
+            None => url.to_string(),
+        }
+    }
+
+    /// Download the cover at `url` and make its thumbnail, unless that was
+    /// done before. Returns whether anything was downloaded
+    pub async fn download(&mut self, ctx: &AppContext, url: &str) -> Result<bool, String> {
+        if self.dir.is_empty() || self.index.contains_key(url) {
+            return Ok(false);
+        }
+
+        let res = ctx
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            return Err(format!("HTTP {}", res.status()));
+        }
+        let bytes = res.bytes().await.map_err(|e| e.to_string())?;
+
+        let format = image::guess_format(&bytes).map_err(|_| "Not an image".to_string())?;
+        let image = ImageReader::with_format(Cursor::new(&bytes), format)
+            .decode()
+            .map_err(|e| format!("Failed to decode: {}", e))?;
+        let thumbnail = image.thumbnail(self.thumbnail_size, self.thumbnail_size);
+        let mut thumbnail_bytes = Vec::new();
+        thumbnail
+            .to_rgb8()
+            .write_to(&mut Cursor::new(&mut thumbnail_bytes), ImageFormat::Jpeg)
+            .map_err(|e| format!("Failed to encode the thumbnail: {}", e))?;
+
+        // Named after the url, so a changed cover url is a new file and
+        // readers never see a stale copy under the old name
+        let hash = hex::encode(Sha256::digest(url.as_bytes()));
+        let name = &hash[..16];
+        let extension = format.extensions_str().first().unwrap_or(&"img");
+        let cover = CachedCover {
+            file: format!("{}.{}", name, extension),
+            thumbnail: format!("{}_thumb.jpg", name),
+            mime_type: format.to_mime_type().to_string(),
+            length: bytes.len() as u64,
+        };
+        write_atomic(&format!("{}/{}", self.dir, cover.file), &bytes)?;
+        write_atomic(
+            &format!("{}/{}", self.dir, cover.thumbnail),
+            &thumbnail_bytes,
+        )?;
+
+        self.index.insert(url.to_string(), cover);
+        Ok(true)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = format!("{}/{}", self.dir, INDEX_FILE);
+        let data = serde_json::to_string_pretty(&self.index)
+            .map_err(|e| format!("Failed to serialize {}: {}", path, e))?;
+        write_atomic(&path, data.as_bytes())
+    }
+}
+
+/// Download the covers at `urls` that aren't in `paths.covers` yet.
+/// A cover that fails is logged and keeps being hotlinked. Nothing is
+/// downloaded on a dry run or with `covers.enabled` off
+pub async fn cache_covers(ctx: &AppContext, urls: &[&str], dry_run: bool) -> Result<usize, String> {
+    if dry_run || !ctx.config.covers.enabled {
+        return Ok(0);
+    }
+
+    let mut covers = Covers::load(ctx)?;
+    let mut downloaded = 0;
+    for url in urls {
+        if url.is_empty() {
+            continue;
+        }
+        match covers.download(ctx, url).await {
+            Ok(true) => downloaded += 1,
+            Ok(false) => {}
+            Err(e) => eprintln!("Failed to download the cover {}: {}", url, e),
+        }
+    }
+
+    if downloaded > 0 {
+        covers.save()?;
+    }
+    Ok(downloaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::app::AppConfig;
+    use image::{DynamicImage, RgbImage};
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn test_cache_covers() {
+        let server = MockServer::start().await;
+        let cover = png(600, 900);
+        // Served as a .jpg with the wrong content type
+        Mock::given(method("GET"))
+            .and(path("/cover.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(cover.clone(), "text/plain"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/gone.png"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let (ctx, root) = AppContext::temp("covers", AppConfig::default());
+        let url = format!("{}/cover.jpg", server.uri());
+        let gone = format!("{}/gone.png", server.uri());
+        let urls = [url.as_str(), gone.as_str()];
+
+        assert_eq!(cache_covers(&ctx, &urls, false).await.unwrap(), 1);
+        // Already downloaded
+        assert_eq!(cache_covers(&ctx, &urls, false).await.unwrap(), 0);
+
+        let covers = Covers::load(&ctx).unwrap();
+        let cached = covers.get(&url).unwrap();
+        assert_eq!(cached.mime_type, "image/png");
+        assert!(cached.file.ends_with(".png"));
+        assert_eq!(
+            covers.link(&url),
+            CoverLink {
+                url: format!("{}/{}", ctx.config.urls.covers_base, cached.file),
+                length: cover.len() as u64,
+                mime_type: "image/png".to_string(),
+            }
+        );
+        let thumbnail =
+            image::open(root.path().join("feed/covers").join(&cached.thumbnail)).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (213, 320));
+
+        // Still hotlinked
+        assert_eq!(covers.link(&gone).url, gone);
+        assert_eq!(covers.thumbnail_url(&gone), gone);
+    }
+}
@@ -1 +1,5 @@
+pub mod anilist;
+pub mod cover_resolver;
+pub mod covers;
+pub mod run_report;
 pub mod watchlist;
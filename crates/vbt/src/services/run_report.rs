@@ -0,0 +1,248 @@
+use crate::{
+    requests::get_data::{FetchStats, TableNotFound},
+    utils::fs::write_atomic,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{error::Error, time::Duration};
+
+/// How an entry went
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryStatus {
+    Ok,
+    /// The registry has no rows for it
+    Empty,
+    /// It couldn't be fetched
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct EntryReport {
+    pub name: String,
+    pub slug: String,
+    pub status: EntryStatus,
+    /// See [`error_kind`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub rows: usize,
+    pub new: usize,
+    pub changed: usize,
+    pub removed: usize,
+    #[serde(flatten)]
+    pub fetch: FetchStats,
+    pub fetch_ms: u64,
+    /// Covers, JSON and the feed
+    pub process_ms: u64,
+}
+
+/// Sums over the entries of a run
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Totals {
+    /// Entries the run was meant to go through, a stopped run reports fewer
+    pub entries: usize,
+    pub ok: usize,
+    pub empty: usize,
+    pub failed: usize,
+    pub rows: usize,
+    pub new: usize,
+    pub changed: usize,
+    pub removed: usize,
+    #[serde(flatten)]
+    pub fetch: FetchStats,
+}
+
+/// What a run did, written to `paths.runs` as `<id>.json` so CI and the
+/// pages site can keep a history and notice regressions
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RunReport {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Why the run stopped early, if it did
+    pub error: Option<String>,
+    pub totals: Totals,
+    pub entries: Vec<EntryReport>,
+}
+
+impl EntryReport {
+    /// An entry that was fetched in `fetch_time` with `fetch` requests
+    pub fn new(name: &str, slug: String, fetch: FetchStats, fetch_time: Duration) -> Self {
+        EntryReport {
+            name: name.to_string(),
+            slug,
+            status: EntryStatus::Ok,
+            error_kind: None,
+            error: None,
+            rows: 0,
+            new: 0,
+            changed: 0,
+            removed: 0,
+            fetch,
+            fetch_ms: millis(fetch_time),
+            process_ms: 0,
+        }
+    }
+}
+
+impl RunReport {
+    pub fn new(id: i64) -> Self {
+        let now = Utc::now();
+        RunReport {
+            id,
+            started_at: now,
+            finished_at: now,
+            duration_ms: 0,
+            error: None,
+            totals: Totals::default(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Stamp the end of a run over `entries` entries and add up the totals
+    pub fn finish(&mut self, entries: usize, error: Option<String>) {
+        self.finished_at = Utc::now();
+        self.duration_ms = (self.finished_at - self.started_at)
+            .num_milliseconds()
+            .max(0) as u64;
+        self.error = error;
+
+        let mut totals = Totals {
+            entries,
+            ..Default::default()
+        };
+        for entry in &self.entries {
+            match entry.status {
+                EntryStatus::Ok => totals.ok += 1,
+                EntryStatus::Empty => totals.empty += 1,
+                EntryStatus::Failed => totals.failed += 1,
+            }
+            totals.rows += entry.rows;
+            totals.new += entry.new;
+            totals.changed += entry.changed;
+            totals.removed += entry.removed;
+            totals.fetch.requests += entry.fetch.requests;
+            totals.fetch.retries += entry.fetch.retries;
+        }
+        self.totals = totals;
+    }
+
+    /// Write the report into `dir`, returning its path
+    pub fn save(&self, dir: &str) -> Result<String, String> {
+        let path = format!("{}/{}.json", dir, self.id);
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize {}: {}", path, e))?;
+        write_atomic(&path, format!("{}\n", data).as_bytes())?;
+        Ok(path)
+    }
+}
+
+/// A short name for what made a fetch fail, stable enough to group and
+/// alert on: `no_table`, `timeout`, `connect`, `status`, `body`, `request`
+/// or `other`
+pub fn error_kind(error: &(dyn Error + 'static)) -> &'static str {
+    if error.is::<TableNotFound>() {
+        return "no_table";
+    }
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(e) if e.is_timeout() => "timeout",
+        Some(e) if e.is_connect() => "connect",
+        Some(e) if e.is_status() => "status",
+        Some(e) if e.is_body() || e.is_decode() => "body",
+        Some(_) => "request",
+        None => "other",
+    }
+}
+
+pub(crate) fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::TempDir;
+    use serde_json::json;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_report() {
+        let mut report = RunReport::new(1_700_000_000);
+        let mut ok = EntryReport::new(
+            "Dược sư tự sự",
+            "Kusuriya_no_Hitorigoto".to_string(),
+            FetchStats {
+                requests: 3,
+                retries: 2,
+            },
+            Duration::from_millis(1500),
+        );
+        ok.rows = 12;
+        ok.new = 1;
+        ok.changed = 2;
+
+        // Nothing listens on port 1
+        let refused = reqwest::get("http://127.0.0.1:1").await.unwrap_err();
+        let mut failed = EntryReport::new(
+            "Other",
+            "Other".to_string(),
+            FetchStats {
+                requests: 1,
+                retries: 0,
+            },
+            Duration::ZERO,
+        );
+        failed.status = EntryStatus::Failed;
+        failed.error_kind = Some(error_kind(&refused));
+        failed.error = Some(refused.to_string());
+        report.entries = vec![ok, failed];
+        report.finish(3, None);
+
+        assert_eq!(
+            report.totals,
+            Totals {
+                entries: 3,
+                ok: 1,
+                empty: 0,
+                failed: 1,
+                rows: 12,
+                new: 1,
+                changed: 2,
+                removed: 0,
+                fetch: FetchStats {
+                    requests: 4,
+                    retries: 2,
+                },
+            }
+        );
+        assert_eq!(error_kind(&*Box::<dyn Error>::from("Table")), "other");
+
+        let dir = TempDir::new("runs");
+        let path = report.save(dir.path().to_str().unwrap()).unwrap();
+        assert!(path.ends_with("/1700000000.json"));
+        let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["totals"]["requests"], 4);
+        assert_eq!(
+            saved["entries"][0],
+            json!({
+                "name": "Dược sư tự sự",
+                "slug": "Kusuriya_no_Hitorigoto",
+                "status": "ok",
+                "rows": 12,
+                "new": 1,
+                "changed": 2,
+                "removed": 0,
+                "requests": 3,
+                "retries": 2,
+                "fetch_ms": 1500,
+                "process_ms": 0,
+            })
+        );
+        assert_eq!(saved["entries"][1]["status"], "failed");
+        assert_eq!(saved["entries"][1]["error_kind"], "connect");
+    }
+}
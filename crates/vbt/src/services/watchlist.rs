@@ -1,21 +1,37 @@
-use crate::types::watchlist::WatchlistEntry;
-use serde_json::Value;
+use crate::{context::AppContext, types::watchlist::WatchlistEntry, utils::fs::write_atomic};
+use serde_json::{json, Value};
 use std::{collections::HashMap, fs::File};
 
-pub fn load_watchlist() -> Result<Vec<WatchlistEntry>, String> {
-    let file = File::open("watchlist.json")
-        .map_err(|e| format!("Failed to open watchlist.json: {}", e))?;
+/// Order of the alternative titles in `other`
+const OTHER_KEYS: [&str; 3] = ["native", "romaji", "english"];
 
-    let json: Value = serde_json::from_reader(file)
-        .map_err(|e| format!("Failed to parse watchlist.json: {}", e))?;
+fn read_watchlist(path: &str) -> Result<Vec<Value>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
 
+    let json: Value =
+        serde_json::from_reader(file).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+
+    match json {
+        Value::Array(entries) => Ok(entries),
+        _ => Err(format!("{} must hold an array of entries", path)),
+    }
+}
+
+fn write_watchlist(path: &str, entries: Vec<Value>) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(&Value::Array(entries))
+        .map_err(|e| format!("Failed to serialize {}: {}", path, e))?;
+    write_atomic(path, format!("{}\n", data).as_bytes())
+}
+
+pub fn load_watchlist(ctx: &AppContext) -> Result<Vec<WatchlistEntry>, String> {
+    let path = &ctx.path(&ctx.config.paths.watchlist);
     let mut entries = Vec::new();
 
-    for entry in json.as_array().unwrap() {
+    for entry in read_watchlist(path)? {
         let mut other_titles = HashMap::new();
         if let Some(other) = entry["other"].as_array() {
             for title in other {
-                for key in &["native", "romaji", "english"] {
+                for key in OTHER_KEYS {
                     if let Some(value) = title[key].as_str() {
                         other_titles.insert(key.to_string(), value.to_string());
                     }
@@ -23,12 +39,136 @@ pub fn load_watchlist() -> Result<Vec<WatchlistEntry>, String> {
             }
         }
 
+        let strings = |key: &str| -> Vec<String> {
+            entry[key]
+                .as_array()
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|value| value.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let name = entry["name"]
+            .as_str()
+            .ok_or_else(|| format!("An entry in {} has no name", path))?;
+        let cover = entry["cover"]
+            .as_str()
+            .ok_or_else(|| format!("Entry {} in {} has no cover", name, path))?;
+
         entries.push(WatchlistEntry {
-            name: entry["name"].as_str().unwrap().to_string(),
-            cover: entry["cover"].as_str().unwrap().to_string(),
+            name: name.to_string(),
+            cover: cover.to_string(),
             other: other_titles,
+            tags: strings("tags"),
+            queries: strings("queries"),
+            anilist: entry["anilist"].as_u64(),
+            interval_hours: entry["interval_hours"].as_u64(),
+            ..Default::default()
         });
     }
 
     Ok(entries)
 }
+
+/// The JSON written to the watchlist for `entry`
+fn entry_to_value(entry: &WatchlistEntry) -> Value {
+    let other: Vec<Value> = OTHER_KEYS
+        .iter()
+        .filter_map(|key| entry.other.get(*key).map(|title| json!({ *key: title })))
+        .collect();
+
+    let mut value = json!({
+        "name": entry.name,
+        "other": other,
+        "cover": entry.cover,
+    });
+    if !entry.tags.is_empty() {
+        value["tags"] = json!(entry.tags);
+    }
+    if !entry.queries.is_empty() {
+        value["queries"] = json!(entry.queries);
+    }
+    if let Some(anilist) = entry.anilist {
+        value["anilist"] = json!(anilist);
+    }
+    if let Some(hours) = entry.interval_hours {
+        value["interval_hours"] = json!(hours);
+    }
+    value
+}
+
+/// Append `entry` to the watchlist, names must be unique
+pub fn add_to_watchlist(
+    ctx: &AppContext,
+    entry: &WatchlistEntry,
+    dry_run: bool,
+) -> Result<(), String> {
+    let path = &ctx.path(&ctx.config.paths.watchlist);
+    let mut entries = read_watchlist(path)?;
+    if entries
+        .iter()
+        .any(|e| e["name"].as_str() == Some(&entry.name))
+    {
+        return Err(format!("{} is already in the watchlist", entry.name));
+    }
+
+    entries.push(entry_to_value(entry));
+    if dry_run {
+        return Ok(());
+    }
+    write_watchlist(path, entries)
+}
+
+/// Set the registry queries of the entry called `name`
+pub fn set_queries(
+    ctx: &AppContext,
+    name: &str,
+    queries: &[String],
+    dry_run: bool,
+) -> Result<(), String> {
+    let path = &ctx.path(&ctx.config.paths.watchlist);
+    let mut entries = read_watchlist(path)?;
+    let entry = entries
+        .iter_mut()
+        .find(|e| e["name"].as_str() == Some(name))
+        .ok_or_else(|| format!("{} is not in the watchlist", name))?;
+
+    match entry.as_object_mut() {
+        Some(object) => {
+            object.insert("queries".to_string(), json!(queries));
+        }
+        None => return Err(format!("Entry {} in {} is not an object", name, path)),
+    }
+    if dry_run {
+        return Ok(());
+    }
+    write_watchlist(path, entries)
+}
+
+/// Remove the entry called `name`, or whose feed is named `name`.
+/// Returns the removed entry
+pub fn remove_from_watchlist(
+    ctx: &AppContext,
+    name: &str,
+    dry_run: bool,
+) -> Result<WatchlistEntry, String> {
+    let watchlist = load_watchlist(ctx)?;
+    let index = watchlist
+        .iter()
+        .position(|entry| entry.name == name || entry.slug() == name)
+        .ok_or_else(|| format!("{} is not in the watchlist", name))?;
+
+    if !dry_run {
+        let path = &ctx.path(&ctx.config.paths.watchlist);
+        let mut entries = read_watchlist(path)?;
+        entries.remove(index);
+        write_watchlist(path, entries)?;
+    }
+    Ok(watchlist
+        .into_iter()
+        .nth(index)
+        .expect("index is in bounds"))
+}
@@ -0,0 +1,414 @@
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+// https://core.telegram.org/bots/api#sendmessage
+pub const MAX_MESSAGE: usize = 4096;
+// https://core.telegram.org/bots/api#sendphoto
+pub const MAX_CAPTION: usize = 1024;
+
+/// Attempts per request, 429 and 5xx responses are retried
+const MAX_ATTEMPTS: u32 = 3;
+/// Upper bound for a single `retry_after` wait
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize)]
+struct SendMessage<'a> {
+    chat_id: &'a str,
+    text: &'a str,
+    parse_mode: &'static str,
+    disable_web_page_preview: bool,
+}
+
+#[derive(Serialize)]
+struct SendPhoto<'a> {
+    chat_id: &'a str,
+    /// Url of the photo, Telegram downloads it itself
+    photo: &'a str,
+    caption: &'a str,
+    parse_mode: &'static str,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse {
+    ok: bool,
+    result: Option<Message>,
+    description: Option<String>,
+    parameters: Option<ResponseParameters>,
+}
+
+#[derive(Deserialize)]
+struct ResponseParameters {
+    retry_after: Option<u64>,
+}
+
+/// The part of a sent message we care about
+#[derive(Deserialize, Debug, Clone)]
+pub struct Message {
+    pub message_id: i64,
+}
+
+/// Client for the Telegram Bot API
+pub struct TelegramBot {
+    client: Client,
+    api_url: String,
+    token: String,
+}
+
+impl TelegramBot {
+    pub fn new(token: impl Into<String>) -> Self {
+        TelegramBot {
+            client: Client::new(),
+            api_url: DEFAULT_API_URL.to_string(),
+            token: token.into(),
+        }
+    }
+
+    /// Share `client`, and its connection pool, with the rest of the app
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Talk to another Bot API server, e.g. a self-hosted one
+    pub fn api_url(mut self, api_url: impl Into<String>) -> Self {
+        self.api_url = api_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// `sendMessage` with HTML formatting. `text` must fit in [`MAX_MESSAGE`]
+    pub async fn send_message(&self, chat_id: &str, text: &str) -> Result<Message, String> {
+        let body = SendMessage {
+            chat_id,
+            text,
+            parse_mode: "HTML",
+            disable_web_page_preview: true,
+        };
+        self.call("sendMessage", &body).await
+    }
+
+    /// `sendPhoto` with an HTML caption. `caption` must fit in [`MAX_CAPTION`]
+    pub async fn send_photo(
+        &self,
+        chat_id: &str,
+        photo: &str,
+        caption: &str,
+    ) -> Result<Message, String> {
+        let body = SendPhoto {
+            chat_id,
+            photo,
+            caption,
+            parse_mode: "HTML",
+        };
+        self.call("sendPhoto", &body).await
+    }
+
+    /// Send `text` with `photo` on top. What doesn't fit in the caption
+    /// follows as messages, and when the photo is rejected (e.g. a dead cover
+    /// link) everything is sent as messages. Returns the sent message ids
+    pub async fn send_with_photo(
+        &self,
+        chat_id: &str,
+        photo: &str,
+        text: &str,
+    ) -> Result<Vec<i64>, String> {
+        let mut ids = Vec::new();
+        let mut rest = text.to_string();
+
+        if !photo.is_empty() {
+            let (caption, remainder) = split_first(text, MAX_CAPTION);
+            match self.send_photo(chat_id, photo, &caption).await {
+                Ok(message) => {
+                    ids.push(message.message_id);
+                    rest = remainder;
+                }
+                Err(e) => eprintln!("Failed to send Telegram photo, sending text only: {}", e),
+            }
+        }
+
+        for chunk in split_text(&rest, MAX_MESSAGE) {
+            ids.push(self.send_message(chat_id, &chunk).await?.message_id);
+        }
+
+        Ok(ids)
+    }
+
+    async fn call<T: Serialize>(&self, method: &str, body: &T) -> Result<Message, String> {
+        let url = format!("{}/bot{}/{}", self.api_url, self.token, method);
+        let mut last_error = String::new();
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let response = match self.client.post(&url).json(body).send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    // The url holds the bot token, keep it out of the logs
+                    last_error = format!("request failed: {}", e.without_url());
+                    tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+                    continue;
+                }
+            };
+
+            let status = response.status();
+            let api: ApiResponse = match response.json().await {
+                Ok(api) => api,
+                Err(e) => {
+                    last_error = format!("Telegram returned {}: {}", status, e.without_url());
+                    if status.is_server_error() {
+                        tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+                        continue;
+                    }
+                    return Err(last_error);
+                }
+            };
+
+            if api.ok {
+                return api
+                    .result
+                    .ok_or_else(|| format!("Telegram {} returned no message", method));
+            }
+
+            last_error = format!(
+                "Telegram {} returned {}: {}",
+                method,
+                status,
+                api.description.unwrap_or_default()
+            );
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let secs = api.parameters.and_then(|p| p.retry_after).unwrap_or(1);
+                tokio::time::sleep(Duration::from_secs(secs).min(MAX_WAIT)).await;
+            } else if status.is_server_error() {
+                tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+            } else {
+                return Err(last_error);
+            }
+        }
+
+        Err(format!(
+            "gave up after {} attempts: {}",
+            MAX_ATTEMPTS, last_error
+        ))
+    }
+}
+
+/// Escape text for `parse_mode: HTML`, quotes included so the result can
+/// also go in an attribute such as `href="..."`
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Split `text` into chunks of at most `max` characters, breaking between
+/// lines so formatting tags stay balanced. Longer lines are cut with
+/// [`cut_html`]
+fn split_text(text: &str, max: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut rest = text.to_string();
+    while !rest.trim().is_empty() {
+        let (chunk, remainder) = split_first(&rest, max);
+        chunks.push(chunk);
+        rest = remainder;
+    }
+    chunks
+}
+
+/// The first chunk of [`split_text`] and whatever is left after it
+fn split_first(text: &str, max: usize) -> (String, String) {
+    let mut chunk = String::new();
+    let mut len = 0;
+    let mut lines = text.lines().peekable();
+
+    while let Some(line) = lines.peek() {
+        let line_len = line.chars().count();
+        let needed = if chunk.is_empty() {
+            line_len
+        } else {
+            line_len + 1
+        };
+        if len + needed > max {
+            if chunk.is_empty() {
+                // A single line that doesn't fit on its own
+                chunk = cut_html(line, max);
+                lines.next();
+            }
+            break;
+        }
+        if !chunk.is_empty() {
+            chunk.push('\n');
+        }
+        chunk.push_str(line);
+        len += needed;
+        lines.next();
+    }
+
+    (chunk, lines.collect::<Vec<_>>().join("\n"))
+}
+
+/// Cut the HTML `line` to at most `max` characters ending in "…". Tags and
+/// entities are never cut in half, and the tags left open are closed
+fn cut_html(line: &str, max: usize) -> String {
+    let closing = |open: &[&str]| open.iter().map(|tag| tag.len() + 3).sum::<usize>();
+    let mut cut = String::new();
+    let mut len = 0;
+    let mut open: Vec<&str> = Vec::new();
+    let mut rest = line;
+
+    while let Some(c) = rest.chars().next() {
+        let end = match c {
+            '<' => rest.find('>').map_or(rest.len(), |i| i + 1),
+            '&' => rest
+                .find(';')
+                .filter(|&i| !rest[..i].contains(char::is_whitespace))
+                .map_or(1, |i| i + 1),
+            _ => c.len_utf8(),
+        };
+        let token = &rest[..end];
+        let mut next_open = open.clone();
+        if token.starts_with("</") {
+            next_open.pop();
+        } else if c == '<' {
+            let name = token[1..]
+                .split(|c: char| c == '>' || c.is_whitespace())
+                .next()
+                .unwrap_or_default();
+            next_open.push(name);
+        }
+
+        let token_len = token.chars().count();
+        if len + token_len + 1 + closing(&next_open) > max {
+            break;
+        }
+        cut.push_str(token);
+        len += token_len;
+        open = next_open;
+        rest = &rest[end..];
+    }
+
+    cut.push('…');
+    for tag in open.iter().rev() {
+        cut.push_str(&format!("</{}>", tag));
+    }
+    cut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{body_partial_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn ok(message_id: i64) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "ok": true,
+            "result": { "message_id": message_id, "chat": { "id": 1 } }
+        }))
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"https://example.com/?q="Tom" & <Jerry>"#),
+            "https://example.com/?q=&quot;Tom&quot; &amp; &lt;Jerry&gt;"
+        );
+    }
+
+    #[test]
+    fn test_split_text() {
+        let text = format!("{}\n{}\n{}", "a".repeat(6), "b".repeat(3), "c".repeat(12));
+        assert_eq!(split_text(&text, 10), vec!["aaaaaa\nbbb", "ccccccccc…"]);
+        assert_eq!(
+            split_first("one\ntwo", 5),
+            ("one".to_string(), "two".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cut_html() {
+        let line = "• <b>Tom &amp; Jerry</b> <a href=\"https://example.com\">Registry</a>";
+        assert_eq!(cut_html(line, 12), "• <b>To…</b>");
+        // Neither the entity nor the link tag is cut in half
+        assert_eq!(cut_html(line, 18), "• <b>Tom …</b>");
+        assert_eq!(cut_html(line, 19), "• <b>Tom &amp;…</b>");
+        assert_eq!(cut_html(line, 40), "• <b>Tom &amp; Jerry</b> …");
+        assert_eq!(
+            cut_html(line, 61),
+            "• <b>Tom &amp; Jerry</b> <a href=\"https://example.com\">R…</a>"
+        );
+
+        let text = format!("<b>Entry</b>\n<i>{}</i>", "x".repeat(20));
+        let chunks = split_text(&text, 16);
+        assert_eq!(chunks, vec!["<b>Entry</b>", "<i>xxxxxxxx…</i>"]);
+        assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 16));
+    }
+
+    #[tokio::test]
+    async fn test_send_with_photo() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/botTOKEN/sendPhoto"))
+            .and(body_partial_json(serde_json::json!({
+                "chat_id": "@vbt",
+                "photo": "https://example.com/cover.jpg",
+                "caption": "<b>Entry</b>",
+                "parse_mode": "HTML"
+            })))
+            .respond_with(ok(1))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bot = TelegramBot::new("TOKEN").api_url(server.uri());
+        let ids = bot
+            .send_with_photo("@vbt", "https://example.com/cover.jpg", "<b>Entry</b>")
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_message_and_retries() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/botTOKEN/sendPhoto"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "ok": false,
+                "error_code": 400,
+                "description": "Bad Request: wrong file identifier/HTTP URL specified"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/botTOKEN/sendMessage"))
+            .respond_with(ResponseTemplate::new(429).set_body_json(serde_json::json!({
+                "ok": false,
+                "error_code": 429,
+                "description": "Too Many Requests: retry after 0",
+                "parameters": { "retry_after": 0 }
+            })))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/botTOKEN/sendMessage"))
+            .and(body_partial_json(
+                serde_json::json!({ "text": "<b>Entry</b>" }),
+            ))
+            .respond_with(ok(2))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bot = TelegramBot::new("TOKEN").api_url(server.uri());
+        let ids = bot
+            .send_with_photo("@vbt", "https://example.com/dead.jpg", "<b>Entry</b>")
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![2]);
+    }
+}
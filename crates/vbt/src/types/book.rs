@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct BookRow {
     pub stt: String,
     pub isbn: String,
@@ -13,6 +13,19 @@ pub struct BookRow {
     pub registration_number: String,
 }
 
+#[cfg(test)]
+impl BookRow {
+    /// A row with only an ISBN and a title, as tests mostly need
+    pub(crate) fn sample(isbn: &str, title: &str) -> Self {
+        BookRow {
+            stt: "1".to_string(),
+            isbn: isbn.to_string(),
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct BookEntry {
     pub pub_date: String,
@@ -1,8 +1,54 @@
+use crate::utils::cache::sanitize_filename;
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub struct WatchlistEntry {
     pub name: String,
     pub cover: String,
     pub other: HashMap<String, String>,
+    /// Free form labels, used to route notifications
+    pub tags: Vec<String>,
+    /// Registry queries, written by `vbt discover`. The name when empty
+    pub queries: Vec<String>,
+    /// AniList media id, enables enrichment
+    pub anilist: Option<u64>,
+    /// Hours between checks by `vbt daemon`, `daemon.entry_interval_hours`
+    /// when unset
+    pub interval_hours: Option<u64>,
+    /// The fields below are only filled by AniList enrichment
+    pub synonyms: Vec<String>,
+    /// `ln`, `manga` or `oneshot`
+    pub format: Option<String>,
+    /// e.g. `releasing` or `finished`
+    pub status: Option<String>,
+    pub genres: Vec<String>,
+}
+
+impl WatchlistEntry {
+    /// File name (without extension) used for this entry's feeds.
+    /// Uses the romaji title if available, otherwise the name
+    pub fn slug(&self) -> String {
+        let filename_base = self
+            .other
+            .get("romaji")
+            .unwrap_or(&self.name)
+            .replace(" ", "_");
+        sanitize_filename(filename_base)
+    }
+
+    /// What the registry is searched for
+    pub fn queries(&self) -> Vec<&str> {
+        if self.queries.is_empty() {
+            vec![self.name.as_str()]
+        } else {
+            self.queries.iter().map(String::as_str).collect()
+        }
+    }
+
+    /// Alternate titles sorted and joined with ", "
+    pub fn alt_titles(&self) -> String {
+        let mut titles: Vec<_> = self.other.values().map(|value| value.to_string()).collect();
+        titles.sort();
+        titles.join(", ")
+    }
 }
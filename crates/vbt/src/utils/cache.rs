@@ -1,11 +1,16 @@
-use crate::types::book::BookRow;
+use crate::{types::book::BookRow, utils::fs::write_atomic};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::File};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, ErrorKind},
+};
 
-#[derive(Serialize, Deserialize)]
+/// Entries are kept sorted so the file only changes where the data does
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Cache<T> {
-    pub data: HashMap<String, T>,
-    pub title_build_date: HashMap<String, T>,
+    pub data: BTreeMap<String, T>,
+    pub title_build_date: BTreeMap<String, T>,
 }
 
 pub fn sanitize_filename(name: impl Into<String>) -> String {
@@ -21,23 +26,59 @@ pub fn generate_cache_key(book: &BookRow) -> String {
     format!("{}-{}", book.isbn, book.title)
 }
 
-pub fn load_cache<T: for<'de> Deserialize<'de>>(path: &str) -> Cache<T> {
-    if let Ok(file) = File::open(path) {
-        if let Ok(cache) = serde_json::from_reader(file) {
-            return cache;
+/// Load the cache at `path`. A missing file gives an empty cache,
+/// a file that can't be read or parsed is an error
+pub fn load_cache<T: for<'de> Deserialize<'de>>(path: &str) -> Result<Cache<T>, String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Ok(Cache {
+                data: BTreeMap::new(),
+                title_build_date: BTreeMap::new(),
+            })
         }
-    }
-    Cache {
-        data: HashMap::new(),
-        title_build_date: HashMap::new(),
-    }
+        Err(e) => return Err(format!("Failed to open cache file {}: {}", path, e)),
+    };
+
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+        format!(
+            "Cache file {} is corrupt ({}), fix or remove it before running again",
+            path, e
+        )
+    })
 }
 
 pub fn save_cache<T: Serialize>(cache: &Cache<T>, path: &str) -> Result<(), String> {
-    let file = File::create(path).map_err(|e| format!("Failed to create cache file: {}", e))?;
+    let data =
+        serde_json::to_vec_pretty(cache).map_err(|e| format!("Failed to write cache: {}", e))?;
+
+    write_atomic(path, &data)
+}
 
-    serde_json::to_writer_pretty(file, cache)
-        .map_err(|e| format!("Failed to write cache: {}", e))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::TempDir;
+    use std::fs;
 
-    Ok(())
+    #[test]
+    fn test_load_cache() {
+        let dir = TempDir::new("cache");
+        let path = dir.file("date_cache.json");
+
+        let mut cache = load_cache::<String>(&path).unwrap();
+        assert!(cache.data.is_empty() && cache.title_build_date.is_empty());
+
+        cache
+            .data
+            .insert("isbn-Title".to_string(), "date".to_string());
+        save_cache(&cache, &path).unwrap();
+        assert!(load_cache::<String>(&path).unwrap() == cache);
+
+        // A truncated file must not be taken for an empty cache, or every
+        // book would be published again
+        fs::write(&path, "{\"data\": {").unwrap();
+        let e = load_cache::<String>(&path).err().unwrap();
+        assert!(e.starts_with(&format!("Cache file {} is corrupt", path)));
+    }
 }
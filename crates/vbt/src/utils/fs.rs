@@ -1,9 +1,75 @@
-use std::{fs, path::Path};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-pub fn ensure_parent_dir(path: &str) -> Result<(), String> {
-    if let Some(parent) = Path::new(path).parent() {
+/// Tells apart the temporary files of concurrent writes in one process
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// Write `data` to a temporary file next to `path`, fsync it, rename it
+/// over `path` and fsync the directory, so a crash never leaves a
+/// half-written file behind. The temporary file is removed if anything fails
+pub fn write_atomic(path: &str, data: &[u8]) -> Result<(), String> {
+    let target = Path::new(path);
+    let parent = target.parent().filter(|p| !p.as_os_str().is_empty());
+    if let Some(parent) = parent {
         fs::create_dir_all(parent)
             .map_err(|e| format!("Failed to create directory {}: {}", parent.display(), e))?;
     }
+
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| format!("Invalid file path: {}", path))?;
+    // Unique per process and per write, two writers never share a file
+    let tmp_path = target.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = File::create_new(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, target));
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(format!("Failed to write {}: {}", path, e));
+    }
+
+    // Persist the rename itself. The new file is in place either way, so
+    // a failure here is only reported
+    #[cfg(unix)]
+    if let Err(e) = File::open(parent.unwrap_or(Path::new("."))).and_then(|dir| dir.sync_all()) {
+        eprintln!("Failed to sync the directory of {}: {}", path, e);
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::TempDir;
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = TempDir::new("fs");
+        let path = dir.file("nested/state.json");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        // A rename that fails leaves no temporary file
+        let e = write_atomic(&dir.file("nested"), b"x").unwrap_err();
+        assert!(e.starts_with(&format!("Failed to write {}", dir.file("nested"))));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        assert_eq!(fs::read_dir(dir.path().join("nested")).unwrap().count(), 1);
+    }
+}
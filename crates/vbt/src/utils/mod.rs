@@ -1,4 +1,5 @@
 pub mod cache;
 pub mod fs;
+pub mod schedule;
 pub mod time;
 pub mod url;
@@ -0,0 +1,95 @@
+use chrono::{DateTime, Duration, TimeZone};
+use std::str::FromStr;
+
+/// When `vbt daemon` wakes up, in the configured time zone
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Cron(Box<cron::Schedule>),
+    /// Counted from local midnight, so `6h` is 00:00, 06:00, 12:00 and 18:00
+    Every(Duration),
+}
+
+impl Schedule {
+    /// A cron expression, with or without the seconds field, or an
+    /// interval such as `90s`, `30m`, `6h` or `1d`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if let Some(interval) = parse_interval(value) {
+            return match interval {
+                interval if interval > Duration::zero() => Ok(Schedule::Every(interval)),
+                _ => Err(format!("{} is not a positive interval", value)),
+            };
+        }
+
+        let expression = match value.split_whitespace().count() {
+            5 => format!("0 {}", value),
+            _ => value.to_string(),
+        };
+        cron::Schedule::from_str(&expression)
+            .map(|schedule| Schedule::Cron(Box::new(schedule)))
+            .map_err(|e| {
+                format!(
+                    "{} is neither an interval nor a cron expression: {}",
+                    value, e
+                )
+            })
+    }
+
+    /// The first wake up strictly after `after`
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            Schedule::Cron(schedule) => schedule.after(after).next(),
+            Schedule::Every(interval) => {
+                let midnight = after
+                    .timezone()
+                    .from_local_datetime(&after.date_naive().and_hms_opt(0, 0, 0)?)
+                    .earliest()?;
+                let elapsed = after.clone() - midnight.clone();
+                let steps = elapsed.num_milliseconds() / interval.num_milliseconds() + 1;
+                Some(midnight + *interval * steps as i32)
+            }
+        }
+    }
+}
+
+/// `<number><s|m|h|d>`
+fn parse_interval(value: &str) -> Option<Duration> {
+    let unit = value.chars().last()?;
+    let number: i64 = value[..value.len() - unit.len_utf8()].trim().parse().ok()?;
+    match unit {
+        's' => Duration::try_seconds(number),
+        'm' => Duration::try_minutes(number),
+        'h' => Duration::try_hours(number),
+        'd' => Duration::try_days(number),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Asia::Ho_Chi_Minh;
+
+    #[test]
+    fn test_next_after() {
+        let now = Ho_Chi_Minh.with_ymd_and_hms(2024, 11, 3, 7, 30, 0).unwrap();
+        let next = |schedule: &str| {
+            Schedule::parse(schedule)
+                .unwrap()
+                .next_after(&now)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(next("6h"), "2024-11-03 12:00:00 +07");
+        assert_eq!(next("90m"), "2024-11-03 09:00:00 +07");
+        assert_eq!(next("1d"), "2024-11-04 00:00:00 +07");
+        // Local time, not UTC
+        assert_eq!(next("0 8 * * *"), "2024-11-03 08:00:00 +07");
+        assert_eq!(next("0 0 6,18 * * *"), "2024-11-03 18:00:00 +07");
+
+        assert!(Schedule::parse("0h").is_err());
+        assert!(Schedule::parse("6 hours").is_err());
+        assert!(Schedule::parse("* * *").is_err());
+    }
+}
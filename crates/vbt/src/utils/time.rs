@@ -1,13 +1,11 @@
-use chrono::{FixedOffset, Utc};
+use chrono::Utc;
+use chrono_tz::Tz;
 
-pub fn generate_time() -> String {
-    let now_utc = Utc::now();
-    let vietnam_offset = FixedOffset::east_opt(7 * 3600).unwrap();
-    now_utc.with_timezone(&vietnam_offset).to_rfc2822()
+/// The current time in `tz`, for message footers
+pub fn generate_time(tz: Tz) -> String {
+    Utc::now().with_timezone(&tz).to_rfc2822()
 }
 
 pub fn generate_unix_timestamp() -> i64 {
-    let now_utc = Utc::now();
-    let vietnam_offset = FixedOffset::east_opt(7 * 3600).unwrap();
-    now_utc.with_timezone(&vietnam_offset).timestamp()
+    Utc::now().timestamp()
 }
@@ -1,4 +1,11 @@
 use std::path::Path;
+
+/// Registry search page for `query`, the link given for an entry.
+/// `registry` is the search page without a query, see `urls.registry`
+pub fn registry_search_url(registry: &str, query: &str) -> String {
+    format!("{}?query={}", registry, query.trim_end().replace(" ", "+"))
+}
+
 // I have no idea about the name, stick with url for now
 pub fn get_mime_type(url: &str) -> &str {
     match Path::new(url).extension().and_then(|e| e.to_str()) {